@@ -1,57 +1,110 @@
 use clap::Parser;
-use dickens::escape_name_for_graphviz;
+use dickens::{
+    abbs::{expand_build, host_arch, read_tree},
+    depend::{expand, fetch_index, read_index, BuildPlan, DependKind, PackageIndex},
+    graph::{Edge, Graph as DepGraph, GraphFormat, Node},
+};
 use graph_cycles::Cycles;
-use log::info;
-use petgraph::Graph;
-use std::{collections::BTreeMap, fs::File, io::Write, path::PathBuf, process::Command};
+use petgraph::{algo::tarjan_scc, Graph};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Package names
+    /// Package names, all packages of the ABBS tree if empty with --abbs-tree
     packages: Vec<String>,
 
-    /// Dump dependency graph in graphviz format
+    /// Follow BUILDDEP and PKGDEP in autobuild/defines of this ABBS tree instead
+    /// of runtime dependencies in Packages indexes
+    #[clap(long, conflicts_with_all = ["index", "branch"])]
+    abbs_tree: Option<PathBuf>,
+
+    /// Read this Packages index instead of fetching from the repository, can be
+    /// repeated
+    #[clap(short, long)]
+    index: Vec<PathBuf>,
+
+    /// Branch of the repository to fetch Packages indexes from
+    #[clap(short, long, default_value = "stable")]
+    branch: String,
+
+    /// Architecture to fetch Packages indexes for, along with `all`, or of
+    /// overrides like BUILDDEP__AMD64, defaults to the host
+    #[clap(short, long)]
+    arch: Option<String>,
+
+    /// Dump dependency graph as DOT, Mermaid (.mmd) or JSON (.json), chosen by
+    /// the extension or --graph-format
+    #[clap(short, long)]
+    graph: Option<PathBuf>,
+
+    /// Format of the dependency graph: dot, mermaid or json, defaults to the
+    /// extension of its path
+    #[clap(long, value_parser = GraphFormat::from_str, requires = "graph")]
+    graph_format: Option<GraphFormat>,
+
+    /// Write the build order and parallel build levels as JSON, `-` for stdout
+    #[clap(short, long)]
+    order: Option<PathBuf>,
+
+    /// Print packages that can be built in parallel, level by level
     #[clap(short, long)]
-    graph: PathBuf,
+    levels: bool,
+}
+
+/// Print human readable output, to stderr if the build order is written to stdout
+macro_rules! out {
+    ($opt:expr, $($arg:tt)*) => {
+        if $opt.order_to_stdout() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
 }
 
-#[derive(Debug)]
-struct Package {
-    name: String,
-    depends: Vec<String>,
+impl Cli {
+    /// Architecture given with --arch, or of the host
+    fn arch(&self) -> anyhow::Result<&str> {
+        match self.arch.as_deref() {
+            Some(arch) => Ok(arch),
+            None => host_arch(),
+        }
+    }
+
+    fn order_to_stdout(&self) -> bool {
+        self.order.as_deref() == Some(Path::new("-"))
+    }
 }
 
-fn main() -> anyhow::Result<()> {
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
     env_logger::init();
     let opt = Cli::parse();
 
-    let mut known: BTreeMap<String, Package> = BTreeMap::new();
-    let mut todos = opt.packages.clone();
-
-    while let Some(todo) = todos.pop() {
-        if known.contains_key(&todo) {
-            continue;
-        }
-        info!("Handling package {}", todo);
-
-        let output = Command::new("apt").arg("depends").arg(&todo).output()?;
-        assert!(output.status.success());
-
-        let mut cur = Package {
-            name: todo.clone(),
-            depends: vec![],
-        };
-        for line in String::from_utf8(output.stdout)?.lines() {
-            if line.starts_with("  Depends:") {
-                let pkg = line.split(" ").nth(3).unwrap();
-                todos.push(pkg.to_string());
-                cur.depends.push(pkg.to_string());
+    let expansion = if let Some(tree) = &opt.abbs_tree {
+        expand_build(&read_tree(tree)?, &opt.packages, opt.arch()?)
+    } else {
+        let mut index = PackageIndex::default();
+        if opt.index.is_empty() {
+            for arch in [opt.arch()?, "all"] {
+                index.add(fetch_index(arch, &opt.branch).await?);
+            }
+        } else {
+            for path in &opt.index {
+                index.add(read_index(path)?);
             }
         }
-
-        known.insert(todo, cur);
+        expand(&index, &opt.packages)
+    };
+    for finding in &expansion.findings {
+        out!(opt, "{}", finding);
     }
+    let known = &expansion.depends;
 
     // loop detection
     let packages: Vec<&String> = known.keys().collect();
@@ -60,37 +113,83 @@ fn main() -> anyhow::Result<()> {
         pkg_to_index.insert(*pkg, index);
     }
     let mut edges = vec![];
-    for (name, pkg) in &known {
-        for depend in &pkg.depends {
-            edges.push((pkg_to_index[name] as u32, pkg_to_index[&depend] as u32));
+    for (name, depends) in known {
+        for depend in depends {
+            edges.push((
+                pkg_to_index[name] as u32,
+                pkg_to_index[&depend.package] as u32,
+            ));
         }
     }
     let deps = Graph::<(), ()>::from_edges(&edges);
-    deps.visit_all_cycles(|_g, c| {
-        let mut edges: Vec<&str> = c.iter().map(|idx| packages[idx.index()].as_str()).collect();
-        edges.push(edges[0]);
-        println!("Found dependency cycle: {}", edges.join(" -> "));
-    });
-
-    let mut file = File::create(opt.graph)?;
-    writeln!(file, "digraph G {{")?;
-    for (name, pkg) in known {
-        writeln!(
-            file,
-            "  {} [label=\"{}\"];",
-            escape_name_for_graphviz(&name),
-            name
-        )?;
-        for depend in pkg.depends {
-            writeln!(
-                file,
-                "  {} -> {};",
-                escape_name_for_graphviz(&name),
-                escape_name_for_graphviz(&depend)
-            )?;
+    if opt.abbs_tree.is_some() && opt.packages.is_empty() {
+        // enumerating every cycle of a whole tree takes exponential time, so
+        // only report the strongly connected components
+        for component in tarjan_scc(&deps) {
+            if component.len() == 1 && deps.find_edge(component[0], component[0]).is_none() {
+                continue;
+            }
+            let mut names: Vec<&str> = component
+                .iter()
+                .map(|idx| packages[idx.index()].as_str())
+                .collect();
+            names.sort();
+            out!(opt, "Found dependency cycle among: {}", names.join(", "));
+        }
+    } else {
+        deps.visit_all_cycles(|_g, c| {
+            let mut edges: Vec<&str> = c.iter().map(|idx| packages[idx.index()].as_str()).collect();
+            edges.push(edges[0]);
+            out!(opt, "Found dependency cycle: {}", edges.join(" -> "));
+        });
+    }
+
+    if opt.order.is_some() || opt.levels {
+        let plan = BuildPlan::new(&expansion);
+        if opt.levels {
+            for (level, units) in plan.levels.iter().enumerate() {
+                let units: Vec<String> = units
+                    .iter()
+                    .map(|i| {
+                        let unit = &plan.order[*i];
+                        if unit.bootstrap {
+                            format!("{{{}}} (bootstrap)", unit.packages.join(" "))
+                        } else {
+                            unit.packages.join(" ")
+                        }
+                    })
+                    .collect();
+                out!(opt, "Level {}: {}", level, units.join(", "));
+            }
+        }
+        match &opt.order {
+            Some(path) if path == Path::new("-") => println!("{}", plan.to_json()?),
+            Some(path) => std::fs::write(path, plan.to_json()?)?,
+            None => {}
+        }
+    }
+
+    let Some(path) = &opt.graph else {
+        return Ok(());
+    };
+    let mut graph = DepGraph::default();
+    for (name, depends) in known {
+        graph.add_node(Node::new(name, name));
+        for depend in depends {
+            let mut edge = Edge::new(name, &depend.package);
+            // show how the dependency is written unless it is just the name
+            let label = match depend.dependency.kind {
+                DependKind::Depends => depend.dependency.to_string(),
+                DependKind::PreDepends => format!("Pre-Depends: {}", depend.dependency),
+                DependKind::BuildDepends => format!("BUILDDEP: {}", depend.dependency),
+            };
+            if label != depend.package {
+                edge = edge.label(&label);
+            }
+            graph.add_edge(edge);
         }
     }
-    writeln!(file, "}}")?;
+    graph.save(path, opt.graph_format)?;
 
     Ok(())
 }
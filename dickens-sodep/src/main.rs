@@ -1,145 +1,734 @@
-use clap::Parser;
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 use dickens::{
-    escape_name_for_graphviz,
-    sodep::{get_libraries, get_library_deps},
+    abbs::{host_arch, read_tree},
+    graph::{Edge, Graph, GraphFormat, Node},
+    sodep::{
+        check, find_version_requirements,
+        finding::{Finding, Findings, Kind, Severity},
+        index::RepoIndex,
+        is_installed, library_closure,
+        provider::ProviderIndex,
+        resolve::{read_ld_so_conf, Resolver, SearchPath},
+        ClosureNode, DebIndex, LibraryFile, PackageSource, Scanner, SonameIndex,
+    },
 };
-use log::{error, warn};
+use log::{info, warn};
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fs::File,
-    io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Json,
+    Sarif,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FailOn {
+    Error,
+    Warning,
+    Never,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Index sonames of every package in a repository, reporting sonames shipped
+    /// by more than one package and NEEDED entries no package provides
+    Audit {
+        /// Packages index of the repository
+        index: PathBuf,
+
+        /// Directory containing .deb files of the repository
+        #[clap(long, default_value = "debs")]
+        debs: PathBuf,
+
+        /// Write the soname index as JSON
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(group(ArgGroup::new("providers").args(["suggest", "abbs_tree", "recursive"]).multiple(true)))]
 struct Cli {
-    /// Package name
-    package: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Dependent package names
+    /// Package name or path to .deb
+    #[clap(required = true)]
+    package: Option<String>,
+
+    /// Dependent package names or paths to .deb
     depends: Vec<String>,
 
-    /// Dump dependency graph in graphviz format
+    /// Resolve package names to .deb files with this Packages index instead of
+    /// using installed packages
+    #[clap(short, long)]
+    index: Option<PathBuf>,
+
+    /// Number of files to scan concurrently, defaults to the number of CPUs
+    #[clap(short, long, global = true)]
+    jobs: Option<usize>,
+
+    /// Path to the scan cache, defaults to ~/.cache/dickens/sodep.json
+    #[clap(long, global = true)]
+    cache: Option<PathBuf>,
+
+    /// Do not read or write the scan cache
+    #[clap(long, global = true, conflicts_with = "cache")]
+    no_cache: bool,
+
+    /// Package assumed to be always available, can be repeated, overrides the
+    /// builtins file
+    #[clap(long = "builtin")]
+    builtins: Vec<String>,
+
+    /// Read builtin packages from this file, one per line, defaults to
+    /// ~/.config/dickens/builtins if it exists
+    #[clap(long, conflicts_with = "builtins")]
+    builtins_file: Option<PathBuf>,
+
+    /// Do not assume any package to be available
+    #[clap(long, conflicts_with_all = ["builtins", "builtins_file"])]
+    no_builtins: bool,
+
+    /// Suggest packages providing missing sonames and print the resulting PKGDEP
+    #[clap(short, long)]
+    suggest: bool,
+
+    /// Look up providers in this Contents index instead of installed packages,
+    /// or in Contents-ARCH of this directory
+    #[clap(long, requires = "providers")]
+    contents: Option<PathBuf>,
+
+    /// Check the package against PKGDEP in its autobuild/defines from this ABBS
+    /// tree, declared packages are added to the dependencies
+    #[clap(long)]
+    abbs_tree: Option<PathBuf>,
+
+    /// Architecture for overrides like PKGDEP__AMD64 and of the Contents
+    /// index, defaults to the host
+    #[clap(long)]
+    arch: Option<String>,
+
+    /// Report NEEDED libraries whose symbols are never used, and dependencies
+    /// only reachable transitively
+    #[clap(long)]
+    overlinking: bool,
+
+    /// Follow NEEDED entries into libraries of dependencies, and print the
+    /// shared library closure of each file as a tree
+    #[clap(short, long)]
+    recursive: bool,
+
+    /// Extra library directories are read from this file, defaults to
+    /// /etc/ld.so.conf if it exists
+    #[clap(long)]
+    ld_so_conf: Option<PathBuf>,
+
+    /// Print how each NEEDED entry is resolved, grouped by search path
+    #[clap(long)]
+    show_resolution: bool,
+
+    /// Print the highest symbol version required from each NEEDED library, and
+    /// the resulting versioned dependencies
+    #[clap(long)]
+    symbol_versions: bool,
+
+    /// Write findings to this file, `-` for stdout
+    #[clap(long, global = true)]
+    report: Option<PathBuf>,
+
+    /// Format of the findings report
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = ReportFormat::Json,
+        requires = "report"
+    )]
+    report_format: ReportFormat,
+
+    /// Exit with 2 on errors and 3 on warnings, only for findings at least this
+    /// severe. By default findings do not change the exit status, other
+    /// failures exit with 1
+    #[clap(long, global = true, value_enum, default_value_t = FailOn::Never)]
+    fail_on: FailOn,
+
+    /// Dump dependency graph as DOT, Mermaid (.mmd) or JSON (.json), chosen by
+    /// the extension or --graph-format
     #[clap(short, long)]
     graph: Option<PathBuf>,
+
+    /// Format of the dependency graph: dot, mermaid or json, defaults to the
+    /// extension of its path
+    #[clap(long, value_parser = GraphFormat::from_str, requires = "graph")]
+    graph_format: Option<GraphFormat>,
+
+    /// Include builtin packages in the dependency graph
+    #[clap(long, requires = "graph")]
+    graph_builtins: bool,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::init();
-    let mut opt = Cli::parse();
+/// Print human readable output, to stderr if the report is written to stdout
+macro_rules! out {
+    ($opt:expr, $($arg:tt)*) => {
+        if $opt.report_to_stdout() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+impl Cli {
+    /// Architecture given with --arch, or of the host
+    fn arch(&self) -> anyhow::Result<&str> {
+        match self.arch.as_deref() {
+            Some(arch) => Ok(arch),
+            None => host_arch(),
+        }
+    }
+
+    fn report_to_stdout(&self) -> bool {
+        self.report.as_deref() == Some(Path::new("-"))
+    }
+}
+
+fn print_tree(opt: &Cli, nodes: &[ClosureNode], prefix: &str) {
+    for (i, node) in nodes.iter().enumerate() {
+        let last = i + 1 == nodes.len();
+        let desc = match (&node.package, node.repeated) {
+            (None, _) => "missing".to_string(),
+            (Some(pkg), false) => pkg.clone(),
+            (Some(pkg), true) => format!("{pkg}, see above"),
+        };
+        out!(
+            opt,
+            "{}{}{} ({})",
+            prefix,
+            if last { "└── " } else { "├── " },
+            node.soname,
+            desc
+        );
+        print_tree(
+            opt,
+            &node.children,
+            &format!("{}{}", prefix, if last { "    " } else { "│   " }),
+        );
+    }
+}
+
+/// Path to `name` under `$XDG_*_HOME/dickens`, or `~/{fallback}/dickens`
+fn xdg_path(var: &str, fallback: &str, name: &str) -> Option<PathBuf> {
+    let mut path = match std::env::var_os(var) {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let mut dir = PathBuf::from(std::env::var_os("HOME")?);
+            dir.push(fallback);
+            dir
+        }
+    };
+    path.push("dickens");
+    path.push(name);
+    Some(path)
+}
+
+fn default_cache_path() -> Option<PathBuf> {
+    xdg_path("XDG_CACHE_HOME", ".cache", "sodep.json")
+}
+
+/// Package names from a builtins file, ignoring blank lines and comments
+fn read_builtins(path: &Path) -> anyhow::Result<BTreeSet<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn get_builtins(opt: &Cli) -> anyhow::Result<BTreeSet<String>> {
+    if opt.no_builtins {
+        return Ok(BTreeSet::new());
+    }
+    if !opt.builtins.is_empty() {
+        return Ok(opt.builtins.iter().cloned().collect());
+    }
+    if let Some(path) = &opt.builtins_file {
+        return read_builtins(path);
+    }
+    match xdg_path("XDG_CONFIG_HOME", ".config", "builtins") {
+        Some(path) if path.exists() => read_builtins(&path),
+        // toolchain packages of AOSC OS
+        _ => Ok(["glibc", "gcc-runtime", "libxcrypt"]
+            .into_iter()
+            .map(str::to_string)
+            .collect()),
+    }
+}
+
+async fn get_sources(
+    index: Option<&DebIndex>,
+    args: &[String],
+) -> anyhow::Result<Vec<PackageSource>> {
+    let mut res = vec![];
+    let mut names = vec![];
+    for arg in args {
+        match index {
+            Some(_) if !arg.ends_with(".deb") => names.push(arg.clone()),
+            _ => res.push(PackageSource::from_arg(arg)?),
+        }
+    }
+    if let Some(index) = index {
+        res.extend(index.resolve(&names).await?);
+    }
+    Ok(res)
+}
+
+/// Read packages providing sonames needed by libraries of `sources` but not found
+/// in them, recursively. Returns the packages read with their libraries, and the
+/// sonames no package provides
+async fn read_providers(
+    scanner: &Scanner,
+    index: Option<&DebIndex>,
+    providers: &ProviderIndex,
+    sources: &[(&str, Vec<LibraryFile>)],
+) -> anyhow::Result<(Vec<(PackageSource, Vec<LibraryFile>)>, BTreeSet<String>)> {
+    let mut known: BTreeSet<String> = sources.iter().map(|(pkg, _)| pkg.to_string()).collect();
+    let mut provided = BTreeSet::new();
+    let mut pending = vec![];
+    fn visit(files: &[LibraryFile], provided: &mut BTreeSet<String>, pending: &mut Vec<String>) {
+        for (path, info) in files {
+            provided.insert(
+                path.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+            );
+            provided.extend(info.soname.clone());
+            pending.extend(info.needed.clone());
+        }
+    }
+    for (_, files) in sources {
+        visit(files, &mut provided, &mut pending);
+    }
+
+    let mut res = vec![];
+    let mut unprovided = BTreeSet::new();
+    while let Some(soname) = pending.pop() {
+        if provided.contains(&soname) || unprovided.contains(&soname) {
+            continue;
+        }
+        let candidates = providers.find(&soname)?;
+        let Some(candidate) = candidates.first() else {
+            unprovided.insert(soname);
+            continue;
+        };
+        // provided even if the package cannot be read below
+        provided.insert(soname.clone());
+        if candidates.iter().any(|candidate| known.contains(candidate)) {
+            continue;
+        }
+
+        known.insert(candidate.clone());
+        info!("Reading {} providing {}", candidate, soname);
+        let read = match get_sources(index, std::slice::from_ref(candidate)).await {
+            Ok(mut sources) => {
+                let source = sources.remove(0);
+                scanner
+                    .get_library_files(&source)
+                    .map(|files| (source, files))
+            }
+            Err(err) => Err(err),
+        };
+        let (source, files) = match read {
+            Ok(read) => read,
+            Err(err) => {
+                warn!("Failed to read {} providing {}: {}", candidate, soname, err);
+                continue;
+            }
+        };
+        visit(&files, &mut provided, &mut pending);
+        res.push((source, files));
+    }
+    Ok((res, unprovided))
+}
+
+/// Check a package against its dependencies
+async fn check(opt: &mut Cli, scanner: &Scanner, findings: &mut Findings) -> anyhow::Result<()> {
+    let mut package = opt.package.clone().unwrap_or_default();
 
     // always assume these packages are available
-    let builtins = ["glibc", "gcc-runtime", "libxcrypt"];
-    for builtin in builtins {
-        if !opt.depends.contains(&builtin.to_string()) {
-            opt.depends.push(builtin.to_string());
+    let builtins = get_builtins(opt)?;
+    if builtins.is_empty() {
+        info!("Builtin packages: none");
+    } else {
+        info!(
+            "Builtin packages: {}",
+            builtins.iter().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+    for builtin in &builtins {
+        if !opt.depends.contains(builtin) {
+            opt.depends.push(builtin.clone());
         }
     }
 
-    // map soname => package
-    let mut sonames: BTreeMap<String, &str> = BTreeMap::new();
-    for pkg in &opt.depends {
-        for lib in get_libraries(pkg)? {
-            if let Some(p) = sonames.insert(lib.clone(), pkg) {
-                if p != pkg {
-                    warn!("{lib} appears in both {p} and {pkg}");
-                }
+    let index = opt.index.as_deref().map(DebIndex::read).transpose()?;
+    let target_source = get_sources(index.as_ref(), std::slice::from_ref(&package))
+        .await?
+        .remove(0);
+    // use package names from now on
+    package = target_source.name().to_string();
+
+    let mut depend_sources = get_sources(index.as_ref(), &opt.depends).await?;
+
+    // runtime dependencies declared in the ABBS tree
+    let mut declared: BTreeSet<String> = BTreeSet::new();
+    if let Some(tree) = &opt.abbs_tree {
+        let all_defines = read_tree(tree)?;
+        let Some(defines) = all_defines.get(&package) else {
+            anyhow::bail!("{} is not found in {}", package, tree.display())
+        };
+        let pkgdep = defines.depends("PKGDEP", opt.arch()?);
+        info!(
+            "PKGDEP of {} in {}: {}",
+            package,
+            defines.path.display(),
+            pkgdep.join(" ")
+        );
+        // dependencies given as .deb files take precedence
+        let mut extra = vec![];
+        for dep in &pkgdep {
+            if depend_sources.iter().any(|source| source.name() == dep) {
+                continue;
+            }
+            let available = match &index {
+                Some(index) => index.contains(dep),
+                None => is_installed(dep),
+            };
+            if available {
+                extra.push(dep.clone());
+            } else {
+                findings.push(
+                    Finding::new(
+                        Kind::UnavailableDependency,
+                        format!(
+                            "Package {} is declared in PKGDEP but {}",
+                            dep,
+                            if index.is_some() {
+                                "not found in the index"
+                            } else {
+                                "not installed"
+                            }
+                        ),
+                    )
+                    .package(dep),
+                );
             }
         }
+        depend_sources.extend(get_sources(index.as_ref(), &extra).await?);
+        declared.extend(pkgdep);
+    }
+
+    opt.depends = depend_sources
+        .iter()
+        .map(|source| source.name().to_string())
+        .collect();
+
+    let ld_so_conf = match &opt.ld_so_conf {
+        Some(path) => read_ld_so_conf(path)?,
+        None if Path::new("/etc/ld.so.conf").exists() => {
+            read_ld_so_conf(Path::new("/etc/ld.so.conf"))?
+        }
+        None => vec![],
+    };
+    let mut resolver = Resolver::new(ld_so_conf);
+
+    // read the target package only once, for both its libraries and dependencies
+    let (target_files, deps) = scanner.get_library_files_and_deps(&target_source)?;
+    let mut sources = vec![];
+    for source in &depend_sources {
+        sources.push((source.name(), scanner.get_library_files(source)?));
+    }
+    sources.push((target_source.name(), target_files));
+
+    let (libraries, duplicates) = check::index_libraries(&sources, &mut resolver);
+    findings.extend(duplicates);
+
+    let ctx = check::Context {
+        package: &package,
+        builtins: &builtins,
+        declared: &declared,
+    };
+    let resolved = check::resolve_needed(&deps, &resolver, &libraries);
+    findings.extend(resolved.findings);
+    let (depended, missing) = (resolved.depended, resolved.missing);
+
+    let reqs = find_version_requirements(&deps, &libraries);
+    findings.extend(check::missing_symbol_versions(&reqs));
+    let versioned = check::versioned_dependencies(&reqs, &libraries);
+    if opt.symbol_versions {
+        for req in &reqs {
+            out!(
+                opt,
+                "{}: {} requires {}",
+                req.binary,
+                req.needed,
+                req.version
+            );
+        }
+        for dep in versioned.values() {
+            out!(opt, "Suggested versioned dependency: {dep}");
+        }
     }
 
-    let target = get_libraries(&opt.package)?;
-    for lib in &target {
-        if let Some(p) = sonames.insert(lib.clone(), &opt.package) {
-            if p != opt.package {
-                warn!("{lib} appears in both {p} and {}", opt.package);
+    if opt.show_resolution {
+        // map search path => (file, needed, resolution)
+        let mut groups: BTreeMap<SearchPath, Vec<_>> = BTreeMap::new();
+        for (name, needed, resolution) in &resolved.entries {
+            if let Some(resolution) = resolution {
+                groups
+                    .entry(resolution.via)
+                    .or_default()
+                    .push((name, needed, resolution));
+            }
+        }
+        for (via, items) in &groups {
+            out!(opt, "Resolved via {via}:");
+            for (name, needed, resolution) in items {
+                out!(
+                    opt,
+                    "  {name}: {needed} => {} ({})",
+                    resolution.path.display(),
+                    resolution.package
+                );
             }
         }
     }
 
-    let mut file = if let Some(path) = opt.graph {
-        let mut file = File::create(&path)?;
-        writeln!(file, "digraph G {{")?;
-        for depend in &opt.depends {
-            if !builtins.contains(&depend.as_str()) {
-                writeln!(
-                    file,
-                    "  {} [label = \"{}\"];",
-                    escape_name_for_graphviz(depend),
-                    depend
-                )?;
+    if let Some(path) = &opt.graph {
+        // map file => (soname, providing package or none if unresolved)
+        let mut edges: BTreeMap<&str, Vec<(&str, Option<&str>)>> = BTreeMap::new();
+        for (name, needed, resolution) in &resolved.entries {
+            let provider = resolution
+                .as_ref()
+                .filter(|resolution| resolution.via != SearchPath::NameOnly)
+                .map(|resolution| resolution.package);
+            // skip the package itself for graph display
+            if provider != Some(package.as_str()) {
+                edges.entry(name).or_default().push((needed, provider));
             }
         }
-        writeln!(file, "  subgraph cluster_0 {{",)?;
-        writeln!(file, "    label = \"{}\";", opt.package)?;
-        Some(file)
-    } else {
-        None
-    };
 
-    // find missing
-    let mut depended: BTreeSet<&str> = BTreeSet::new();
-    let mut per_pkg_depended: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
-    for lib in get_library_deps(&opt.package)? {
-        let mut cur_depended: BTreeSet<&str> = BTreeSet::new();
-        for needed in lib.needed {
-            match sonames.get(&needed) {
-                Some(pkg) => {
-                    depended.insert(pkg);
-
-                    // skip the package itself for graphviz display
-                    if pkg != &opt.package {
-                        cur_depended.insert(pkg);
+        let shown = |pkg: &str| opt.graph_builtins || !builtins.contains(pkg);
+        let mut graph = Graph::default();
+        for depend in &opt.depends {
+            if shown(depend) {
+                graph.add_node(Node::new(depend, depend));
+            }
+        }
+        for (name, items) in &edges {
+            let id = format!("file:{name}");
+            if items.iter().any(|(_, pkg)| pkg.is_none_or(shown)) {
+                graph.add_node(Node::new(&id, name).cluster(&package));
+            }
+            for (soname, pkg) in items {
+                match pkg {
+                    Some(pkg) if shown(pkg) => graph.add_edge(Edge::new(&id, pkg).label(soname)),
+                    Some(_) => {}
+                    None => {
+                        let missing = format!("missing:{soname}");
+                        graph.add_node(Node::new(&missing, soname).missing());
+                        graph.add_edge(Edge::new(&id, &missing).label(soname).missing());
                     }
                 }
-                None => {
-                    error!(
-                        "Library/executable {} missing depenedency {}",
-                        lib.name, needed
-                    );
-                }
             }
         }
-        per_pkg_depended.insert(lib.name.clone(), cur_depended);
+        graph.save(path, opt.graph_format)?;
     }
 
-    if let Some(file) = &mut file {
-        let mut i = 0;
-        for (name, depends) in &per_pkg_depended {
-            for depend in depends {
-                if !builtins.contains(depend) {
-                    writeln!(file, "    file_{} [label=\"{}\"];", i, name)?;
-                    break;
-                }
-            }
-            i += 1;
-        }
-        writeln!(file, "  }}")?;
-
-        i = 0;
-        for depends in per_pkg_depended.values() {
-            for depend in depends {
-                if !builtins.contains(depend) {
-                    writeln!(
-                        file,
-                        "  file_{} -> {};",
-                        i,
-                        escape_name_for_graphviz(depend)
-                    )?;
-                }
+    findings.extend(check::unused_dependencies(&ctx, &opt.depends, &depended));
+
+    let provider_index = match &opt.contents {
+        Some(contents) => ProviderIndex::from_contents(contents, opt.arch()?)?,
+        None => ProviderIndex::Installed,
+    };
+
+    if opt.recursive {
+        // closures go through libraries of dependencies, which may need packages
+        // not given on the command line, so read those providers as well
+        let (extra, unprovided) =
+            read_providers(scanner, index.as_ref(), &provider_index, &sources).await?;
+        let mut closure_libraries = SonameIndex::default();
+        let all_sources = sources
+            .iter()
+            .map(|(pkg, files)| (*pkg, files))
+            .chain(extra.iter().map(|(source, files)| (source.name(), files)));
+        for (pkg, files) in all_sources {
+            for (path, info) in files {
+                closure_libraries.add(pkg, path, info.clone());
             }
-            i += 1;
         }
 
-        writeln!(file, "}}")?;
+        for lib in &deps {
+            let closure = library_closure(&lib.needed, &lib.arch, &closure_libraries);
+            out!(opt, "{}", lib.name);
+            print_tree(opt, &closure, "");
+            findings.extend(check::transitive_missing(&lib.name, &closure, &unprovided));
+        }
+    }
+
+    if opt.overlinking {
+        findings.extend(check::overlinking(&ctx, &deps, &libraries, &depended));
+    }
+
+    if opt.abbs_tree.is_some() {
+        findings.extend(check::undeclared_dependencies(
+            &ctx,
+            &depended,
+            &missing,
+            &provider_index,
+        )?);
+    }
+
+    if opt.suggest {
+        let (pkgdep, suggest_findings) =
+            check::suggest_pkgdep(&ctx, &depended, &missing, &versioned, &provider_index)?;
+        findings.extend(suggest_findings);
+        out!(opt, "PKGDEP=\"{}\"", pkgdep.join(" "));
     }
+    Ok(())
+}
 
-    for pkg in &opt.depends {
-        if !depended.contains(pkg.as_str()) && !builtins.contains(&pkg.as_str()) {
-            warn!("Package {} is not depended by {}", pkg, opt.package);
+/// Index sonames of all packages in a repository and report conflicts
+fn audit(
+    scanner: &Scanner,
+    index: &Path,
+    debs: &Path,
+    output: Option<&Path>,
+    findings: &mut Findings,
+) -> anyhow::Result<()> {
+    let (sources, missing) = DebIndex::read(index)?.local_debs(debs);
+    if !missing.is_empty() {
+        warn!(
+            "{} packages are not found in {}: {}",
+            missing.len(),
+            debs.display(),
+            missing.join(", ")
+        );
+    }
+
+    let mut repo = RepoIndex::default();
+    for source in &sources {
+        let (libraries, deps) = scanner.get_library_files_and_deps(source)?;
+        repo.add(source.name(), &libraries, &deps);
+    }
+
+    for (soname, arch, packages) in repo.duplicates() {
+        findings.push(
+            Finding::new(
+                Kind::DuplicateProvider,
+                format!(
+                    "{} for {} is shipped by {}",
+                    soname,
+                    arch,
+                    packages.iter().cloned().collect::<Vec<_>>().join(", ")
+                ),
+            )
+            .soname(soname),
+        );
+    }
+    for (soname, arch, packages) in repo.unprovided() {
+        let provided = repo.provided_arches(soname);
+        for package in packages {
+            let finding = if provided.is_empty() {
+                Finding::new(
+                    Kind::NoProvider,
+                    format!("{} needs {} which no package provides", package, soname),
+                )
+            } else {
+                Finding::new(
+                    Kind::ArchMismatch,
+                    format!(
+                        "{} {} needs {}, only {} providers available",
+                        arch,
+                        package,
+                        soname,
+                        provided
+                            .iter()
+                            .map(|arch| arch.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                )
+            };
+            findings.push(finding.soname(soname).package(package));
         }
     }
+
+    if let Some(output) = output {
+        repo.save(output)?;
+    }
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let mut opt = Cli::parse();
+
+    let mut scanner = match opt.jobs {
+        Some(jobs) => Scanner::new(jobs),
+        None => Scanner::default(),
+    };
+    if opt.overlinking {
+        scanner = scanner.with_symbols();
+    }
+    if !opt.no_cache {
+        if let Some(cache) = opt.cache.clone().or_else(default_cache_path) {
+            scanner = scanner.with_cache(&cache)?;
+        }
+    }
+
+    let mut findings = Findings::default();
+    match opt.command.take() {
+        Some(Command::Audit {
+            index,
+            debs,
+            output,
+        }) => audit(&scanner, &index, &debs, output.as_deref(), &mut findings)?,
+        None => check(&mut opt, &scanner, &mut findings).await?,
+    }
+
+    scanner.save_cache()?;
+
+    if let Some(path) = &opt.report {
+        let report = match opt.report_format {
+            ReportFormat::Json => findings.to_json()?,
+            ReportFormat::Sarif => findings.to_sarif("dickens-sodep", env!("CARGO_PKG_VERSION"))?,
+        };
+        if path == Path::new("-") {
+            println!("{report}");
+        } else {
+            std::fs::write(path, report)?;
+        }
+    }
+
+    let threshold = match opt.fail_on {
+        FailOn::Error => Severity::Error,
+        FailOn::Warning => Severity::Warning,
+        FailOn::Never => return Ok(()),
+    };
+    match findings.severity() {
+        Some(severity) if severity >= threshold => {
+            std::process::exit(if severity == Severity::Error { 2 } else { 3 })
+        }
+        _ => Ok(()),
+    }
+}
@@ -1,22 +1,85 @@
 use clap::Parser;
-use dickens::topic::report;
+use dickens::{
+    publish::{GitHubPublisher, Publisher},
+    topic::{diff_snapshots, report, Snapshot},
+};
 use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     /// Topic name
-    topic: String,
+    #[arg(required_unless_present = "compare")]
+    topic: Option<String>,
 
     /// Local path to repo if available
     local_repo: Option<PathBuf>,
+
+    /// Directory to keep the saved snapshot of each topic
+    #[arg(long, default_value = "snapshots")]
+    snapshot_dir: PathBuf,
+
+    /// Only show what changed since the saved snapshot of this topic
+    #[arg(short, long)]
+    incremental: bool,
+
+    /// Save the snapshot of this report, as the baseline of later incremental runs
+    #[arg(short, long)]
+    save_snapshot: bool,
+
+    /// Compare two snapshots instead of generating a report
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"], conflicts_with = "topic")]
+    compare: Option<Vec<PathBuf>>,
+
+    /// Post the report to the pull request of the topic, using GITHUB_TOKEN
+    #[arg(short, long)]
+    publish: bool,
+
+    /// GitHub repository to find the pull request in
+    #[arg(long, default_value = "AOSC-Dev/aosc-os-abbs")]
+    github_repo: String,
+
+    /// GitHub API endpoint
+    #[arg(long, default_value = "https://api.github.com")]
+    github_api: String,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
     let opt = Cli::parse();
-    let out = report(&opt.topic, opt.local_repo).await?;
-    println!("{}", out);
+
+    if let Some(compare) = opt.compare {
+        let old = Snapshot::load(&compare[0])?;
+        let new = Snapshot::load(&compare[1])?;
+        println!("{}", diff_snapshots(&old, &new)?);
+        return Ok(());
+    }
+
+    let topic = opt.topic.unwrap();
+    let (full, snapshot) = report(&topic, opt.local_repo).await?;
+
+    let mut path = opt.snapshot_dir;
+    path.push(format!("{}.json", topic.replace('/', "_")));
+    let changes = if opt.incremental && path.exists() {
+        Some(diff_snapshots(&Snapshot::load(&path)?, &snapshot)?)
+    } else {
+        None
+    };
+    println!("{}", changes.as_ref().unwrap_or(&full));
+    if opt.save_snapshot {
+        snapshot.save(&path)?;
+    }
+
+    if opt.publish {
+        // the published comment is replaced each time, so always keep the full report in it
+        let published = match changes {
+            Some(changes) => format!("{changes}\n{full}"),
+            None => full,
+        };
+        GitHubPublisher::from_env(&opt.github_api, &opt.github_repo)?
+            .publish(&topic, &published)
+            .await?;
+    }
     Ok(())
 }
@@ -0,0 +1,240 @@
+//! Package metadata read from an ABBS tree
+
+use crate::depend::{Constraint, DependEdge, DependKind, Dependency, Expansion, Finding, Relation};
+use abbs_meta_apml::parse;
+use anyhow::anyhow;
+use log::{info, warn};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+/// Variables of an `autobuild/defines` or `spec` file
+#[derive(Debug)]
+pub struct Defines {
+    pub path: PathBuf,
+    pub context: HashMap<String, String>,
+}
+
+impl Defines {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let mut context = HashMap::new();
+        if let Err(vec_err) = parse(&content, &mut context) {
+            let errors: Vec<String> = vec_err.iter().map(|err| err.to_string()).collect();
+            return Err(anyhow!(
+                "{}: Got error {} when parsing",
+                path.display(),
+                errors.join(", ")
+            ));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            context,
+        })
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.context.get("PKGNAME").map(String::as_str)
+    }
+
+    /// Value of `var`, preferring the override for `arch` like `PKGDEP__AMD64`
+    pub fn get(&self, var: &str, arch: &str) -> Option<&str> {
+        self.context
+            .get(&format!("{}__{}", var, arch.to_uppercase()))
+            .or_else(|| self.context.get(var))
+            .map(String::as_str)
+    }
+
+    /// Packages listed in `var`, with version constraints like `libfoo>=1.2`
+    pub fn relations(&self, var: &str, arch: &str) -> Vec<Relation> {
+        self.get(var, arch)
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|dep| {
+                let pos = dep.find(['<', '>', '=']).unwrap_or(dep.len());
+                let (name, rest) = dep.split_at(pos);
+                if name.is_empty() {
+                    return None;
+                }
+                let op_len = rest
+                    .find(|c: char| !matches!(c, '<' | '>' | '='))
+                    .unwrap_or(rest.len());
+                Some(Relation {
+                    name: name.to_string(),
+                    arch: None,
+                    constraint: (!rest.is_empty()).then(|| Constraint {
+                        op: rest[..op_len].to_string(),
+                        version: rest[op_len..].to_string(),
+                    }),
+                })
+            })
+            .collect()
+    }
+
+    /// Package names listed in `var`, without version constraints
+    pub fn depends(&self, var: &str, arch: &str) -> Vec<String> {
+        self.relations(var, arch)
+            .into_iter()
+            .map(|relation| relation.name)
+            .collect()
+    }
+}
+
+/// All defines in an ABBS tree, including split packages, skipping those
+/// failing to parse
+fn all_defines(tree: &Path) -> anyhow::Result<Vec<Defines>> {
+    let mut res = vec![];
+    // section/package/autobuild/defines or section/package/01-split/defines
+    for entry in walkdir::WalkDir::new(tree).max_depth(4).sort_by_file_name() {
+        let file = entry?;
+        if file.file_name() != "defines" {
+            continue;
+        }
+        match Defines::read(file.path()) {
+            Ok(defines) => res.push(defines),
+            Err(err) => warn!("{}", err),
+        }
+    }
+    Ok(res)
+}
+
+/// Map package name => defines of all packages in an ABBS tree
+pub fn read_tree(tree: &Path) -> anyhow::Result<BTreeMap<String, Defines>> {
+    info!("Reading ABBS tree {}", tree.display());
+    let mut res: BTreeMap<String, Defines> = BTreeMap::new();
+    for defines in all_defines(tree)? {
+        let Some(name) = defines.name() else {
+            continue;
+        };
+        if let Some(prev) = res.get(name) {
+            warn!(
+                "{} is defined in both {} and {}, using the latter",
+                name,
+                prev.path.display(),
+                defines.path.display()
+            );
+        }
+        res.insert(name.to_string(), defines);
+    }
+    Ok(res)
+}
+
+/// Follow BUILDDEP and PKGDEP of `roots` through the tree for `arch`, or of all
+/// packages if `roots` is empty
+pub fn expand_build(tree: &BTreeMap<String, Defines>, roots: &[String], arch: &str) -> Expansion {
+    let mut res = Expansion::default();
+    let mut todos: Vec<String> = vec![];
+    if roots.is_empty() {
+        todos.extend(tree.keys().cloned());
+    }
+    for root in roots {
+        if tree.contains_key(root) {
+            todos.push(root.clone());
+        } else {
+            res.findings.push(Finding::UnknownPackage {
+                dependency: root.clone(),
+                required_by: None,
+            });
+        }
+    }
+    let mut seen: BTreeSet<String> = todos.iter().cloned().collect();
+
+    while let Some(todo) = todos.pop() {
+        if res.depends.contains_key(&todo) {
+            continue;
+        }
+        info!("Handling package {}", todo);
+        let Some(defines) = tree.get(&todo) else {
+            continue;
+        };
+
+        let mut edges = vec![];
+        for (var, kind) in [
+            ("BUILDDEP", DependKind::BuildDepends),
+            ("PKGDEP", DependKind::Depends),
+        ] {
+            for relation in defines.relations(var, arch) {
+                if !tree.contains_key(&relation.name) {
+                    res.findings.push(Finding::UnknownPackage {
+                        dependency: relation.to_string(),
+                        required_by: Some(todo.clone()),
+                    });
+                    continue;
+                }
+                if seen.insert(relation.name.clone()) {
+                    todos.push(relation.name.clone());
+                }
+                edges.push(DependEdge {
+                    package: relation.name.clone(),
+                    dependency: Dependency {
+                        alternatives: vec![relation],
+                        kind,
+                    },
+                    provides: None,
+                });
+            }
+        }
+        res.depends.insert(todo, edges);
+    }
+    res
+}
+
+/// Architecture name of AOSC OS for the host
+pub fn host_arch() -> anyhow::Result<&'static str> {
+    let little_endian = cfg!(target_endian = "little");
+    Ok(match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "i486",
+        "aarch64" => "arm64",
+        "loongarch64" => "loongarch64",
+        "riscv64" => "riscv64",
+        "powerpc" => "powerpc",
+        "powerpc64" if little_endian => "ppc64el",
+        "powerpc64" => "ppc64",
+        "mips64" if little_endian => "loongson3",
+        "mips64r6" if little_endian => "mips64r6el",
+        arch => anyhow::bail!(
+            "Unknown host architecture {}, specify one with --arch",
+            arch
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defines(vars: &[(&str, &str)]) -> Defines {
+        Defines {
+            path: PathBuf::from("app/autobuild/defines"),
+            context: vars
+                .iter()
+                .map(|(var, value)| (var.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn relations() {
+        let defines = defines(&[("PKGDEP", "foo>=1.2 bar=1 >=1 baz")]);
+        let relations: Vec<String> = defines
+            .relations("PKGDEP", "amd64")
+            .iter()
+            .map(|relation| relation.to_string())
+            .collect();
+        // a constraint without a name is dropped
+        assert_eq!(relations, ["foo (>= 1.2)", "bar (= 1)", "baz"]);
+        assert_eq!(defines.depends("PKGDEP", "amd64"), ["foo", "bar", "baz"]);
+    }
+
+    #[test]
+    fn relations_arch_override() {
+        let defines = defines(&[("PKGDEP", "foo"), ("PKGDEP__ARM64", "foo<<2 bar")]);
+        assert_eq!(defines.depends("PKGDEP", "amd64"), ["foo"]);
+        let relations = defines.relations("PKGDEP", "arm64");
+        assert_eq!(relations[0].to_string(), "foo (<< 2)");
+        assert_eq!(relations[1].to_string(), "bar");
+        assert!(defines.relations("BUILDDEP", "arm64").is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+// control files that change on every rebuild and are not worth diffing
+const IGNORED_CONTROL_FILES: [&str; 2] = ["control", "md5sums"];
+
+pub(crate) fn decompress<'a, R: Read + 'a>(
+    name: &str,
+    reader: R,
+) -> anyhow::Result<Box<dyn Read + 'a>> {
+    Ok(if name.ends_with(".gz") {
+        Box::new(flate2::read::GzDecoder::new(reader))
+    } else if name.ends_with(".xz") {
+        Box::new(xz2::read::XzDecoder::new(reader))
+    } else if name.ends_with(".zst") {
+        Box::new(zstd::stream::read::Decoder::new(reader)?)
+    } else if name.ends_with(".tar") {
+        Box::new(reader)
+    } else {
+        anyhow::bail!("Unsupported compression of deb member {}", name)
+    })
+}
+
+/// Open the tarball named `{member}.tar[.*]` inside a .deb and hand it to `f`
+pub fn with_tarball<T>(
+    path: &Path,
+    member: &str,
+    f: impl for<'a> FnOnce(&mut tar::Archive<Box<dyn Read + 'a>>) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    match find_tarball(BufReader::new(File::open(path)?), member, f)? {
+        Some(res) => Ok(res),
+        None => anyhow::bail!("{} has no {}.tar member", path.display(), member),
+    }
+}
+
+/// Same as `with_tarball`, reading the .deb from `reader`, `None` if there is
+/// no such member
+fn find_tarball<R: Read, T>(
+    reader: R,
+    member: &str,
+    f: impl for<'a> FnOnce(&mut tar::Archive<Box<dyn Read + 'a>>) -> anyhow::Result<T>,
+) -> anyhow::Result<Option<T>> {
+    let mut archive = ar::Archive::new(reader);
+    let prefix = format!("{member}.tar");
+    while let Some(entry) = archive.next_entry() {
+        let entry = entry?;
+        let name = String::from_utf8_lossy(entry.header().identifier()).to_string();
+        if name.starts_with(&prefix) {
+            let mut tarball = tar::Archive::new(decompress(&name, entry)?);
+            return f(&mut tarball).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Read maintainer scripts, triggers and conffiles from the control tarball of a .deb
+pub fn read_control(path: &Path) -> anyhow::Result<BTreeMap<String, String>> {
+    with_tarball(path, "control", control_files)
+}
+
+fn control_files(
+    tarball: &mut tar::Archive<Box<dyn Read + '_>>,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut res = BTreeMap::new();
+    for entry in tarball.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let name = entry
+            .path()?
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .to_string();
+        if IGNORED_CONTROL_FILES.contains(&name.as_str()) {
+            continue;
+        }
+
+        let mut content = vec![];
+        entry.read_to_end(&mut content)?;
+        res.insert(name, String::from_utf8_lossy(&content).to_string());
+    }
+    Ok(res)
+}
+
+/// Read the `Package` field from the control file of a .deb
+pub fn package_name(path: &Path) -> anyhow::Result<String> {
+    with_tarball(path, "control", |tarball| {
+        for entry in tarball.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.file_name() != Some("control".as_ref()) {
+                continue;
+            }
+
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            for line in content.lines() {
+                if let Some(name) = line.strip_prefix("Package:") {
+                    return Ok(name.trim().to_string());
+                }
+            }
+        }
+        anyhow::bail!("{} has no Package field", path.display())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    /// A control tarball holding `files`, compressed according to `name`
+    fn control_tarball(name: &str, files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o755);
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+        if name.ends_with(".gz") {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(&tar).unwrap();
+            encoder.finish().unwrap()
+        } else if name.ends_with(".xz") {
+            let mut encoder = xz2::write::XzEncoder::new(vec![], 6);
+            encoder.write_all(&tar).unwrap();
+            encoder.finish().unwrap()
+        } else if name.ends_with(".zst") {
+            zstd::stream::encode_all(tar.as_slice(), 0).unwrap()
+        } else {
+            tar
+        }
+    }
+
+    /// A .deb with a single control member named `name`
+    fn deb(name: &str, files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = ar::Builder::new(vec![]);
+        let debian_binary = b"2.0\n";
+        builder
+            .append(
+                &ar::Header::new(b"debian-binary".to_vec(), debian_binary.len() as u64),
+                debian_binary.as_slice(),
+            )
+            .unwrap();
+        let tarball = control_tarball(name, files);
+        builder
+            .append(
+                &ar::Header::new(name.as_bytes().to_vec(), tarball.len() as u64),
+                tarball.as_slice(),
+            )
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn read(deb: Vec<u8>) -> Option<BTreeMap<String, String>> {
+        find_tarball(Cursor::new(deb), "control", control_files).unwrap()
+    }
+
+    #[test]
+    fn control_files_skip_control_and_md5sums() {
+        let files = [
+            ("./control", "Package: foo\n"),
+            ("./md5sums", "00  usr/bin/foo\n"),
+            ("./postinst", "#!/bin/sh\n"),
+            ("conffiles", "/etc/foo.conf\n"),
+        ];
+        let res = read(deb("control.tar.gz", &files)).unwrap();
+        assert_eq!(
+            res,
+            BTreeMap::from([
+                ("conffiles".to_string(), "/etc/foo.conf\n".to_string()),
+                ("postinst".to_string(), "#!/bin/sh\n".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn decompress_members() {
+        let files = [("./prerm", "#!/bin/sh\n")];
+        for name in [
+            "control.tar",
+            "control.tar.gz",
+            "control.tar.xz",
+            "control.tar.zst",
+        ] {
+            let res = read(deb(name, &files)).unwrap();
+            assert_eq!(res["prerm"], "#!/bin/sh\n", "{name}");
+        }
+    }
+
+    #[test]
+    fn unsupported_or_missing_member() {
+        let deb = deb("control.tar.bz2", &[]);
+        assert!(find_tarball(Cursor::new(deb.clone()), "control", control_files).is_err());
+        assert!(find_tarball(Cursor::new(deb), "data", control_files)
+            .unwrap()
+            .is_none());
+    }
+}
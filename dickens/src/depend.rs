@@ -0,0 +1,916 @@
+//! Runtime dependency expansion over Packages indexes
+
+use libaosc::packages::{FetchPackagesAsync, Package, Packages};
+use log::info;
+use petgraph::{algo::tarjan_scc, graph::DiGraph};
+use serde::Serialize;
+use solver::PackageVersion;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    path::Path,
+};
+
+/// Version constraint of a relation, like `>= 1.2`
+#[derive(Debug, Clone, Serialize)]
+pub struct Constraint {
+    pub op: String,
+    pub version: String,
+}
+
+impl Constraint {
+    /// Whether `version` satisfies the constraint, failing on unknown operators.
+    /// `<` and `>` are the obsolete forms of `<=` and `>=` in Debian
+    pub fn satisfied_by(&self, version: &str) -> anyhow::Result<bool> {
+        let accepts: fn(Ordering) -> bool = match self.op.as_str() {
+            "<<" => Ordering::is_lt,
+            "<=" | "<" => Ordering::is_le,
+            "=" | "==" => Ordering::is_eq,
+            ">=" | ">" => Ordering::is_ge,
+            ">>" => Ordering::is_gt,
+            op => anyhow::bail!("Unknown version operator {}", op),
+        };
+        let (Ok(left), Ok(right)) = (
+            PackageVersion::from(version),
+            PackageVersion::from(&self.version),
+        ) else {
+            return Ok(false);
+        };
+        Ok(left.partial_cmp(&right).is_some_and(accepts))
+    }
+}
+
+/// A single package in a dependency, like `libfoo (>= 1.2)`
+#[derive(Debug, Clone, Serialize)]
+pub struct Relation {
+    pub name: String,
+    /// Architecture qualifier like `any` in `python3:any`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constraint: Option<Constraint>,
+}
+
+impl Display for Relation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(arch) = &self.arch {
+            write!(f, ":{}", arch)?;
+        }
+        if let Some(constraint) = &self.constraint {
+            write!(f, " ({} {})", constraint.op, constraint.version)?;
+        }
+        Ok(())
+    }
+}
+
+impl Relation {
+    /// Parse `name:arch (op version)`, the architecture qualifier is optional
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (name, rest) = match s.find(|c: char| c.is_whitespace() || c == '(') {
+            Some(pos) => s.split_at(pos),
+            None => (s, ""),
+        };
+        let (name, arch) = match name.split_once(':') {
+            Some((name, arch)) => (name, Some(arch.to_string())),
+            None => (name, None),
+        };
+        if name.is_empty() {
+            return None;
+        }
+
+        let constraint = rest
+            .trim()
+            .strip_prefix('(')
+            .and_then(|rest| rest.split(')').next())
+            .map(|inner| {
+                let inner = inner.trim();
+                let pos = inner
+                    .find(|c: char| !matches!(c, '<' | '>' | '='))
+                    .unwrap_or(inner.len());
+                Constraint {
+                    op: inner[..pos].to_string(),
+                    version: inner[pos..].trim().to_string(),
+                }
+            });
+        Some(Self {
+            name: name.to_string(),
+            arch,
+            constraint,
+        })
+    }
+}
+
+/// Field a dependency comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependKind {
+    Depends,
+    PreDepends,
+    /// BUILDDEP in ABBS defines
+    BuildDepends,
+}
+
+/// Alternatives separated by `|`, any of which satisfies the dependency
+#[derive(Debug, Clone, Serialize)]
+pub struct Dependency {
+    pub alternatives: Vec<Relation>,
+    pub kind: DependKind,
+}
+
+impl Display for Dependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let alternatives: Vec<String> = self.alternatives.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", alternatives.join(" | "))
+    }
+}
+
+/// Parse a Depends or Pre-Depends field
+pub fn parse_depends(s: &str, kind: DependKind) -> Vec<Dependency> {
+    s.split(',')
+        .map(|dep| Dependency {
+            alternatives: dep.split('|').filter_map(Relation::parse).collect(),
+            kind,
+        })
+        .filter(|dep| !dep.alternatives.is_empty())
+        .collect()
+}
+
+/// How a relation is satisfied
+#[derive(Debug)]
+pub enum Satisfier<'a> {
+    Real(&'a Package),
+    /// Packages providing a virtual package, in name order
+    Virtual(Vec<&'a Package>),
+}
+
+/// Why a relation cannot be satisfied
+#[derive(Debug)]
+pub enum Unsatisfied {
+    /// Neither a package nor a provider has this name
+    Unknown,
+    /// The package exists in this version, which does not satisfy the constraint
+    Version(String),
+    /// The constraint cannot be checked, like with an unknown operator
+    Invalid(String),
+}
+
+/// Latest version of each package in Packages indexes, with virtual packages
+#[derive(Debug, Default)]
+pub struct PackageIndex {
+    packages: BTreeMap<String, Package>,
+    /// Virtual package => (provider, provided version)
+    provides: BTreeMap<String, Vec<(String, Option<String>)>>,
+}
+
+/// Virtual packages in the Provides field of a package
+fn provided(pkg: &Package) -> impl Iterator<Item = Relation> {
+    parse_depends(
+        pkg.provides.as_deref().unwrap_or_default(),
+        DependKind::Depends,
+    )
+    .into_iter()
+    .flat_map(|dep| dep.alternatives)
+}
+
+impl PackageIndex {
+    pub fn add(&mut self, packages: Packages) {
+        for pkg in packages.0 {
+            let newer = self.packages.get(&pkg.package).is_none_or(|cur| {
+                PackageVersion::from(&cur.version).ok() < PackageVersion::from(&pkg.version).ok()
+            });
+            if !newer {
+                continue;
+            }
+
+            // forget what the replaced version provides
+            if let Some(old) = self.packages.get(&pkg.package) {
+                for relation in provided(old) {
+                    if let Some(providers) = self.provides.get_mut(&relation.name) {
+                        providers.retain(|(provider, _)| *provider != old.package);
+                    }
+                }
+            }
+            for relation in provided(&pkg) {
+                self.provides.entry(relation.name).or_default().push((
+                    pkg.package.clone(),
+                    relation.constraint.map(|constraint| constraint.version),
+                ));
+            }
+            self.packages.insert(pkg.package.clone(), pkg);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Package> {
+        self.packages.get(name)
+    }
+
+    /// Pre-Depends and Depends of a package
+    pub fn depends(&self, pkg: &Package) -> Vec<Dependency> {
+        let mut res = parse_depends(
+            pkg.pre_depends.as_deref().unwrap_or_default(),
+            DependKind::PreDepends,
+        );
+        res.extend(parse_depends(
+            pkg.depends.as_deref().unwrap_or_default(),
+            DependKind::Depends,
+        ));
+        res
+    }
+
+    /// Find packages satisfying `relation`, preferring a real package
+    pub fn resolve(&self, relation: &Relation) -> Result<Satisfier<'_>, Unsatisfied> {
+        // unversioned provides never satisfy a versioned relation
+        let satisfied = |version: Option<&str>| match (&relation.constraint, version) {
+            (None, _) => Ok(true),
+            (Some(constraint), Some(version)) => constraint
+                .satisfied_by(version)
+                .map_err(|err| Unsatisfied::Invalid(err.to_string())),
+            (Some(_), None) => Ok(false),
+        };
+
+        let real = self.packages.get(&relation.name);
+        if let Some(pkg) = real {
+            if satisfied(Some(&pkg.version))? {
+                return Ok(Satisfier::Real(pkg));
+            }
+        }
+
+        let mut providers: Vec<&Package> = vec![];
+        for (provider, version) in self.provides.get(&relation.name).into_iter().flatten() {
+            if satisfied(version.as_deref())? {
+                providers.extend(self.packages.get(provider));
+            }
+        }
+        if !providers.is_empty() {
+            providers.sort_by(|a, b| a.package.cmp(&b.package));
+            return Ok(Satisfier::Virtual(providers));
+        }
+
+        match real {
+            Some(pkg) => Err(Unsatisfied::Version(pkg.version.clone())),
+            None => Err(Unsatisfied::Unknown),
+        }
+    }
+}
+
+pub fn read_index(path: &Path) -> anyhow::Result<Packages> {
+    info!("Reading Packages index {}", path.display());
+    let content = std::fs::read(path)?;
+    Ok(content.as_slice().try_into()?)
+}
+
+/// Fetch the Packages index of `arch` in `branch` from the AOSC OS repository
+pub async fn fetch_index(arch: &str, branch: &str) -> anyhow::Result<Packages> {
+    info!("Fetching Packages index of {} in {}", arch, branch);
+    let fetcher = FetchPackagesAsync::new(true, format!("dists/{branch}/main/binary-{arch}"), None);
+    Ok(fetcher.fetch_packages(arch, branch).await?)
+}
+
+/// A dependency that cannot be satisfied by the index
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Finding {
+    /// No alternative names a known package or virtual package
+    UnknownPackage {
+        dependency: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        required_by: Option<String>,
+    },
+    /// Some alternatives are known, but none in a satisfying version
+    UnsatisfiedVersion {
+        dependency: String,
+        found: String,
+        required_by: String,
+    },
+    /// A relation whose version constraint cannot be checked
+    InvalidConstraint {
+        dependency: String,
+        error: String,
+        required_by: String,
+    },
+    /// A root names a virtual package, expanded through one of its providers
+    VirtualRoot {
+        dependency: String,
+        provider: String,
+        providers: Vec<String>,
+    },
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownPackage {
+                dependency,
+                required_by: Some(required_by),
+            } => write!(f, "Unknown package {dependency}, required by {required_by}"),
+            Self::UnknownPackage {
+                dependency,
+                required_by: None,
+            } => write!(f, "Unknown package {dependency}"),
+            Self::UnsatisfiedVersion {
+                dependency,
+                found,
+                required_by,
+            } => write!(
+                f,
+                "Unsatisfied dependency {dependency}, required by {required_by}, found {found}"
+            ),
+            Self::InvalidConstraint {
+                dependency,
+                error,
+                required_by,
+            } => write!(
+                f,
+                "Invalid dependency {dependency}, required by {required_by}: {error}"
+            ),
+            Self::VirtualRoot {
+                dependency,
+                provider,
+                providers,
+            } => write!(
+                f,
+                "Virtual package {dependency} is provided by {}, using {provider}",
+                providers.join(", ")
+            ),
+        }
+    }
+}
+
+/// An edge of the expanded dependency graph
+#[derive(Debug, Clone, Serialize)]
+pub struct DependEdge {
+    /// The dependency as written in the index
+    pub dependency: Dependency,
+    /// The package chosen to satisfy it
+    pub package: String,
+    /// The virtual package it is satisfied through, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provides: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Expansion {
+    /// Package => edges to its dependencies
+    pub depends: BTreeMap<String, Vec<DependEdge>>,
+    pub findings: Vec<Finding>,
+}
+
+/// Follow dependencies of `roots` through the index
+pub fn expand(index: &PackageIndex, roots: &[String]) -> Expansion {
+    let mut res = Expansion::default();
+    let mut todos: Vec<String> = vec![];
+    for root in roots {
+        let relation = Relation {
+            name: root.clone(),
+            arch: None,
+            constraint: None,
+        };
+        match index.resolve(&relation) {
+            Ok(Satisfier::Real(pkg)) => todos.push(pkg.package.clone()),
+            Ok(Satisfier::Virtual(providers)) => {
+                let provider = providers[0].package.clone();
+                res.findings.push(Finding::VirtualRoot {
+                    dependency: root.clone(),
+                    provider: provider.clone(),
+                    providers: providers.iter().map(|pkg| pkg.package.clone()).collect(),
+                });
+                todos.push(provider);
+            }
+            Err(_) => res.findings.push(Finding::UnknownPackage {
+                dependency: root.clone(),
+                required_by: None,
+            }),
+        }
+    }
+    let mut seen: BTreeSet<String> = todos.iter().cloned().collect();
+
+    while let Some(todo) = todos.pop() {
+        if res.depends.contains_key(&todo) {
+            continue;
+        }
+        info!("Handling package {}", todo);
+        let Some(pkg) = index.get(&todo) else {
+            continue;
+        };
+
+        let mut edges = vec![];
+        for dependency in index.depends(pkg) {
+            let mut found = None;
+            let mut versions = vec![];
+            let mut invalid = false;
+            for relation in &dependency.alternatives {
+                match index.resolve(relation) {
+                    Ok(Satisfier::Real(dep)) => {
+                        found = Some((dep.package.clone(), None));
+                        break;
+                    }
+                    Ok(Satisfier::Virtual(providers)) => {
+                        // prefer a provider already in the graph
+                        let provider = providers
+                            .iter()
+                            .find(|provider| seen.contains(&provider.package))
+                            .unwrap_or(&providers[0]);
+                        found = Some((provider.package.clone(), Some(relation.name.clone())));
+                        break;
+                    }
+                    Err(Unsatisfied::Version(version)) => {
+                        versions.push(format!("{} {}", relation.name, version))
+                    }
+                    Err(Unsatisfied::Invalid(error)) => {
+                        invalid = true;
+                        res.findings.push(Finding::InvalidConstraint {
+                            dependency: relation.to_string(),
+                            error,
+                            required_by: todo.clone(),
+                        });
+                    }
+                    Err(Unsatisfied::Unknown) => {}
+                }
+            }
+
+            match found {
+                Some((package, provides)) => {
+                    if seen.insert(package.clone()) {
+                        todos.push(package.clone());
+                    }
+                    edges.push(DependEdge {
+                        dependency,
+                        package,
+                        provides,
+                    });
+                }
+                // already reported
+                None if invalid && versions.is_empty() => {}
+                None if versions.is_empty() => res.findings.push(Finding::UnknownPackage {
+                    dependency: dependency.to_string(),
+                    required_by: Some(todo.clone()),
+                }),
+                None => res.findings.push(Finding::UnsatisfiedVersion {
+                    dependency: dependency.to_string(),
+                    found: versions.join(", "),
+                    required_by: todo.clone(),
+                }),
+            }
+        }
+        res.depends.insert(todo, edges);
+    }
+    res
+}
+
+/// Map package => packages to build before it, see `BuildPlan::new`
+fn build_edges(depends: &BTreeMap<String, Vec<DependEdge>>) -> BTreeMap<&str, BTreeSet<&str>> {
+    let is_build = |edge: &DependEdge| edge.dependency.kind == DependKind::BuildDepends;
+    let runtime_only = !depends.values().flatten().any(is_build);
+
+    let mut res = BTreeMap::new();
+    for (name, edges) in depends {
+        let mut targets = BTreeSet::new();
+        let mut todos: Vec<&str> = vec![];
+        for edge in edges {
+            if runtime_only || is_build(edge) {
+                todos.push(&edge.package);
+            }
+        }
+        // runtime dependencies of build dependencies are installed too
+        while let Some(todo) = todos.pop() {
+            if !targets.insert(todo) || runtime_only {
+                continue;
+            }
+            for edge in depends.get(todo).into_iter().flatten() {
+                if !is_build(edge) {
+                    todos.push(&edge.package);
+                }
+            }
+        }
+        res.insert(name.as_str(), targets);
+    }
+    res
+}
+
+/// Packages built together, more than one if they depend on each other
+#[derive(Debug, Serialize)]
+pub struct BuildUnit {
+    pub packages: Vec<String>,
+    /// In a dependency cycle, so the packages must be bootstrapped together
+    pub bootstrap: bool,
+    /// Units of the same level can be built in parallel
+    pub level: usize,
+    /// Indexes of units in the build order this one depends on
+    pub depends: Vec<usize>,
+}
+
+/// Build order of an expanded dependency graph, with cycles collapsed into units
+#[derive(Debug, Serialize)]
+pub struct BuildPlan {
+    /// Units in topological order, dependencies first
+    pub order: Vec<BuildUnit>,
+    /// Indexes of units in the build order, level by level
+    pub levels: Vec<Vec<usize>>,
+    /// Dependencies left out of the plan
+    pub findings: Vec<Finding>,
+}
+
+impl BuildPlan {
+    /// Order packages by build dependencies, each of which must be built along
+    /// with its runtime dependencies first. Without any build dependencies, as
+    /// for Packages indexes, runtime dependencies order the build instead
+    pub fn new(expansion: &Expansion) -> Self {
+        let depends = &expansion.depends;
+        let mut graph = DiGraph::<&str, ()>::new();
+        let mut nodes = BTreeMap::new();
+        for name in depends.keys() {
+            nodes.insert(name.as_str(), graph.add_node(name.as_str()));
+        }
+        for (name, targets) in build_edges(depends) {
+            for target in targets {
+                if let Some(to) = nodes.get(target) {
+                    graph.update_edge(nodes[name], *to, ());
+                }
+            }
+        }
+
+        // components come in postorder, so dependencies of a unit always
+        // precede it
+        let components = tarjan_scc(&graph);
+        let mut unit_of = HashMap::new();
+        for (i, component) in components.iter().enumerate() {
+            for node in component {
+                unit_of.insert(*node, i);
+            }
+        }
+
+        let mut order: Vec<BuildUnit> = vec![];
+        for (i, component) in components.iter().enumerate() {
+            let mut packages: Vec<String> = component
+                .iter()
+                .map(|node| graph[*node].to_string())
+                .collect();
+            packages.sort();
+
+            let mut bootstrap = component.len() > 1;
+            let mut unit_depends = BTreeSet::new();
+            for node in component {
+                for next in graph.neighbors(*node) {
+                    match unit_of[&next] {
+                        // depends on itself
+                        j if j == i => bootstrap = true,
+                        j => {
+                            unit_depends.insert(j);
+                        }
+                    }
+                }
+            }
+            let level = unit_depends
+                .iter()
+                .map(|j| order[*j].level + 1)
+                .max()
+                .unwrap_or(0);
+            order.push(BuildUnit {
+                packages,
+                bootstrap,
+                level,
+                depends: unit_depends.into_iter().collect(),
+            });
+        }
+
+        let mut levels: Vec<Vec<usize>> = vec![];
+        for (i, unit) in order.iter().enumerate() {
+            if levels.len() <= unit.level {
+                levels.resize(unit.level + 1, vec![]);
+            }
+            levels[unit.level].push(i);
+        }
+        Self {
+            order,
+            levels,
+            findings: expansion.findings.clone(),
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packages given as (name, version, extra fields)
+    fn packages(packages: &[(&str, &str, &str)]) -> Packages {
+        let content: Vec<String> = packages
+            .iter()
+            .map(|(name, version, extra)| {
+                format!(
+                    "Package: {name}\nVersion: {version}\nSection: libs\nArchitecture: amd64\n\
+                     Installed-Size: 1\nMaintainer: Nobody <nobody@example.com>\n\
+                     Filename: pool/{name}_{version}_amd64.deb\nSize: 1\nSHA256: 00\n\
+                     Description: {name}\n{extra}"
+                )
+            })
+            .collect();
+        content.join("\n").as_bytes().try_into().unwrap()
+    }
+
+    fn index(list: &[(&str, &str, &str)]) -> PackageIndex {
+        let mut index = PackageIndex::default();
+        index.add(packages(list));
+        index
+    }
+
+    #[test]
+    fn parse_relation() {
+        let relation = Relation::parse(" python3:any (>= 3.11) ").unwrap();
+        assert_eq!(relation.name, "python3");
+        assert_eq!(relation.arch.as_deref(), Some("any"));
+        let constraint = relation.constraint.as_ref().unwrap();
+        assert_eq!(
+            (constraint.op.as_str(), constraint.version.as_str()),
+            (">=", "3.11")
+        );
+        assert_eq!(relation.to_string(), "python3:any (>= 3.11)");
+
+        let relation = Relation::parse("libfoo(<<2)").unwrap();
+        assert_eq!(relation.name, "libfoo");
+        assert!(relation.arch.is_none());
+        assert_eq!(relation.to_string(), "libfoo (<< 2)");
+
+        assert!(Relation::parse("  ").is_none());
+        assert!(Relation::parse(":any").is_none());
+    }
+
+    #[test]
+    fn parse_alternatives() {
+        let depends = parse_depends("libc6 (>= 2.38), foo | bar:any, ,", DependKind::PreDepends);
+        assert_eq!(depends.len(), 2);
+        assert!(depends.iter().all(|dep| dep.kind == DependKind::PreDepends));
+        assert_eq!(depends[0].to_string(), "libc6 (>= 2.38)");
+        let names: Vec<&str> = depends[1]
+            .alternatives
+            .iter()
+            .map(|relation| relation.name.as_str())
+            .collect();
+        assert_eq!(names, ["foo", "bar"]);
+        assert_eq!(depends[1].to_string(), "foo | bar:any");
+    }
+
+    #[test]
+    fn depends_include_pre_depends() {
+        let index = index(&[("app", "1", "Pre-Depends: dpkg\nDepends: libfoo | libbar\n")]);
+        let depends = index.depends(index.get("app").unwrap());
+        let kinds: Vec<(String, DependKind)> = depends
+            .iter()
+            .map(|dep| (dep.to_string(), dep.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("dpkg".to_string(), DependKind::PreDepends),
+                ("libfoo | libbar".to_string(), DependKind::Depends),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_virtual_provides() {
+        let index = index(&[
+            ("app", "1", "Depends: mail-transport-agent\n"),
+            ("postfix", "1", "Provides: mail-transport-agent\n"),
+            ("exim", "1", "Provides: mail-transport-agent\n"),
+        ]);
+        let expansion = expand(&index, &["app".to_string()]);
+        let edges = &expansion.depends["app"];
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].package, "exim");
+        assert_eq!(edges[0].provides.as_deref(), Some("mail-transport-agent"));
+        assert!(expansion.findings.is_empty());
+
+        let expansion = expand(&index, &["mail-transport-agent".to_string()]);
+        assert!(expansion.depends.contains_key("exim"));
+        assert!(matches!(
+            &expansion.findings[..],
+            [Finding::VirtualRoot { provider, providers, .. }]
+                if provider == "exim" && providers == &["exim", "postfix"]
+        ));
+    }
+
+    #[test]
+    fn expand_unsatisfied_version() {
+        let index = index(&[
+            ("app", "1", "Depends: libfoo (>= 2), missing\n"),
+            ("libfoo", "1", ""),
+            ("libfoo-compat", "1", "Provides: libfoo (= 1)\n"),
+        ]);
+        let expansion = expand(&index, &["app".to_string()]);
+        assert!(expansion.depends["app"].is_empty());
+        assert!(matches!(
+            &expansion.findings[..],
+            [
+                Finding::UnsatisfiedVersion { found, .. },
+                Finding::UnknownPackage { dependency, .. },
+            ] if found == "libfoo 1" && dependency == "missing"
+        ));
+    }
+
+    #[test]
+    fn constraint_operators() {
+        let satisfied = |op: &str, version: &str| {
+            Constraint {
+                op: op.to_string(),
+                version: "2".to_string(),
+            }
+            .satisfied_by(version)
+            .unwrap()
+        };
+        for (op, lower, equal, higher) in [
+            ("<<", true, false, false),
+            ("<=", true, true, false),
+            ("<", true, true, false),
+            ("=", false, true, false),
+            ("==", false, true, false),
+            (">=", false, true, true),
+            (">", false, true, true),
+            (">>", false, false, true),
+        ] {
+            assert_eq!(
+                [satisfied(op, "1"), satisfied(op, "2"), satisfied(op, "3")],
+                [lower, equal, higher],
+                "{}",
+                op
+            );
+        }
+    }
+
+    #[test]
+    fn expand_invalid_constraint() {
+        let index = index(&[
+            ("app", "1", "Depends: libfoo (=> 1), libbar (>= 1)\n"),
+            ("libfoo", "1", ""),
+            ("libbar", "1", ""),
+        ]);
+        let expansion = expand(&index, &["app".to_string()]);
+        let edges: Vec<&str> = expansion.depends["app"]
+            .iter()
+            .map(|edge| edge.package.as_str())
+            .collect();
+        assert_eq!(edges, ["libbar"]);
+        assert!(matches!(
+            &expansion.findings[..],
+            [Finding::InvalidConstraint { dependency, error, .. }]
+                if dependency == "libfoo (=> 1)" && error.contains("=>")
+        ));
+    }
+
+    #[test]
+    fn newer_version_replaces_provides() {
+        let mut index = index(&[("foo", "1", "Provides: bar\n")]);
+        index.add(packages(&[("foo", "2", "Provides: baz\n")]));
+        let relation = |name: &str| Relation::parse(name).unwrap();
+        assert!(matches!(
+            index.resolve(&relation("bar")),
+            Err(Unsatisfied::Unknown)
+        ));
+        assert!(matches!(
+            index.resolve(&relation("baz")),
+            Ok(Satisfier::Virtual(providers)) if providers[0].version == "2"
+        ));
+    }
+
+    /// Expansion of packages given as (name, dependencies)
+    fn expansion(depends: &[(&str, &[&str])]) -> Expansion {
+        let mut res = Expansion::default();
+        for (name, deps) in depends {
+            let edges = deps
+                .iter()
+                .map(|dep| DependEdge {
+                    dependency: parse_depends(dep, DependKind::Depends).remove(0),
+                    package: dep.to_string(),
+                    provides: None,
+                })
+                .collect();
+            res.depends.insert(name.to_string(), edges);
+        }
+        res
+    }
+
+    /// Expansion of an ABBS tree given as (name, BUILDDEP, PKGDEP)
+    fn build_expansion(defines: &[(&str, &[&str], &[&str])]) -> Expansion {
+        let mut res = Expansion::default();
+        for (name, builddep, pkgdep) in defines {
+            let mut edges = vec![];
+            for (deps, kind) in [
+                (builddep, DependKind::BuildDepends),
+                (pkgdep, DependKind::Depends),
+            ] {
+                edges.extend(deps.iter().map(|dep| DependEdge {
+                    dependency: parse_depends(dep, kind).remove(0),
+                    package: dep.to_string(),
+                    provides: None,
+                }));
+            }
+            res.depends.insert(name.to_string(), edges);
+        }
+        res
+    }
+
+    /// Units of a plan as (packages, bootstrap, level)
+    fn units(plan: &BuildPlan) -> Vec<(Vec<&str>, bool, usize)> {
+        plan.order
+            .iter()
+            .map(|unit| {
+                let packages = unit.packages.iter().map(String::as_str).collect();
+                (packages, unit.bootstrap, unit.level)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plan_chain() {
+        let plan = BuildPlan::new(&expansion(&[("a", &["b"]), ("b", &["c"]), ("c", &[])]));
+        assert_eq!(
+            units(&plan),
+            [
+                (vec!["c"], false, 0),
+                (vec!["b"], false, 1),
+                (vec!["a"], false, 2)
+            ]
+        );
+        assert_eq!(plan.order[2].depends, [1]);
+        assert_eq!(plan.levels, [vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn plan_cycle() {
+        let plan = BuildPlan::new(&expansion(&[("a", &["b"]), ("b", &["a", "c"]), ("c", &[])]));
+        assert_eq!(
+            units(&plan),
+            [(vec!["c"], false, 0), (vec!["a", "b"], true, 1)]
+        );
+        assert_eq!(plan.order[1].depends, [0]);
+    }
+
+    #[test]
+    fn plan_build_depends() {
+        // runtime cycles do not need bootstrapping, but runtime dependencies of
+        // build dependencies are built first
+        let plan = BuildPlan::new(&build_expansion(&[
+            ("a", &["b"], &[]),
+            ("b", &[], &["c"]),
+            ("c", &[], &["b"]),
+        ]));
+        assert_eq!(
+            units(&plan),
+            [
+                (vec!["c"], false, 0),
+                (vec!["b"], false, 0),
+                (vec!["a"], false, 1)
+            ]
+        );
+        assert_eq!(plan.order[2].depends, [0, 1]);
+
+        // building a needs b installed, which needs a at runtime
+        let plan = BuildPlan::new(&build_expansion(&[("a", &["b"], &[]), ("b", &[], &["a"])]));
+        assert_eq!(units(&plan), [(vec!["b"], false, 0), (vec!["a"], true, 1)]);
+
+        let plan = BuildPlan::new(&build_expansion(&[("a", &["b"], &[]), ("b", &["a"], &[])]));
+        assert_eq!(units(&plan), [(vec!["a", "b"], true, 0)]);
+    }
+
+    #[test]
+    fn plan_self_loop() {
+        let plan = BuildPlan::new(&expansion(&[("a", &["a"])]));
+        assert_eq!(units(&plan), [(vec!["a"], true, 0)]);
+        assert!(plan.order[0].depends.is_empty());
+    }
+
+    #[test]
+    fn plan_diamond() {
+        let plan = BuildPlan::new(&expansion(&[
+            ("a", &["b", "c"]),
+            ("b", &["d"]),
+            ("c", &["d"]),
+            ("d", &[]),
+        ]));
+        let level_packages: Vec<Vec<&str>> = plan
+            .levels
+            .iter()
+            .map(|level| {
+                let mut packages: Vec<&str> = level
+                    .iter()
+                    .flat_map(|i| &plan.order[*i].packages)
+                    .map(String::as_str)
+                    .collect();
+                packages.sort();
+                packages
+            })
+            .collect();
+        assert_eq!(level_packages, [vec!["d"], vec!["b", "c"], vec!["a"]]);
+        // dependencies always precede their dependents
+        for (i, unit) in plan.order.iter().enumerate() {
+            assert!(unit.depends.iter().all(|j| *j < i));
+        }
+    }
+}
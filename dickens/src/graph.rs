@@ -0,0 +1,280 @@
+//! Dependency graphs rendered as DOT, Mermaid or JSON
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+    path::Path,
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+    Json,
+}
+
+impl GraphFormat {
+    /// Guess from the extension: `.mmd` for Mermaid, `.json` for JSON, DOT otherwise
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("mmd" | "mermaid") => Self::Mermaid,
+            Some("json") => Self::Json,
+            _ => Self::Dot,
+        }
+    }
+}
+
+impl FromStr for GraphFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" | "graphviz" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            "json" => Ok(Self::Json),
+            _ => anyhow::bail!("Unknown graph format {}, expected dot, mermaid or json", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub label: String,
+    /// Nodes in the same cluster are drawn together, under the cluster name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    /// Drawn in red dashed lines, for things that cannot be found
+    pub missing: bool,
+}
+
+impl Node {
+    pub fn new(id: &str, label: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            label: label.to_string(),
+            cluster: None,
+            missing: false,
+        }
+    }
+
+    pub fn cluster(mut self, cluster: &str) -> Self {
+        self.cluster = Some(cluster.to_string());
+        self
+    }
+
+    pub fn missing(mut self) -> Self {
+        self.missing = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub missing: bool,
+}
+
+impl Edge {
+    pub fn new(from: &str, to: &str) -> Self {
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            label: None,
+            missing: false,
+        }
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn missing(mut self) -> Self {
+        self.missing = true;
+        self
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    /// Ids of `nodes`
+    #[serde(skip)]
+    ids: HashSet<String>,
+}
+
+/// Quote a DOT identifier or label
+fn quote_dot(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Quote a Mermaid label
+fn quote_mermaid(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "#quot;"))
+}
+
+impl Graph {
+    /// Add a node, unless one with the same id exists
+    pub fn add_node(&mut self, node: Node) {
+        if self.ids.insert(node.id.clone()) {
+            self.nodes.push(node);
+        }
+    }
+
+    pub fn add_edge(&mut self, edge: Edge) {
+        self.edges.push(edge);
+    }
+
+    /// Map cluster name => nodes, with `None` for nodes outside any cluster
+    fn clusters(&self) -> BTreeMap<Option<&str>, Vec<&Node>> {
+        let mut res: BTreeMap<Option<&str>, Vec<&Node>> = BTreeMap::new();
+        for node in &self.nodes {
+            res.entry(node.cluster.as_deref()).or_default().push(node);
+        }
+        res
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut res = String::from("digraph G {\n");
+        for (i, (cluster, nodes)) in self.clusters().into_iter().enumerate() {
+            let indent = match cluster {
+                Some(cluster) => {
+                    writeln!(res, "  subgraph cluster_{} {{", i).unwrap();
+                    writeln!(res, "    label = {};", quote_dot(cluster)).unwrap();
+                    "    "
+                }
+                None => "  ",
+            };
+            for node in nodes {
+                write!(
+                    res,
+                    "{}{} [label = {}",
+                    indent,
+                    quote_dot(&node.id),
+                    quote_dot(&node.label)
+                )
+                .unwrap();
+                if node.missing {
+                    res.push_str(", color = red, fontcolor = red, style = dashed");
+                }
+                res.push_str("];\n");
+            }
+            if cluster.is_some() {
+                res.push_str("  }\n");
+            }
+        }
+        for edge in &self.edges {
+            write!(
+                res,
+                "  {} -> {}",
+                quote_dot(&edge.from),
+                quote_dot(&edge.to)
+            )
+            .unwrap();
+            let mut attrs = vec![];
+            if let Some(label) = &edge.label {
+                attrs.push(format!("label = {}", quote_dot(label)));
+            }
+            if edge.missing {
+                attrs.push("color = red, style = dashed".to_string());
+            }
+            if !attrs.is_empty() {
+                write!(res, " [{}]", attrs.join(", ")).unwrap();
+            }
+            res.push_str(";\n");
+        }
+        res.push_str("}\n");
+        res
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        // Mermaid ids are restricted, so number the nodes
+        let mut ids: BTreeMap<&str, String> = BTreeMap::new();
+        for node in &self.nodes {
+            let id = format!("n{}", ids.len());
+            ids.entry(&node.id).or_insert(id);
+        }
+        // endpoints without a node of their own
+        let mut implicit = vec![];
+        for edge in &self.edges {
+            for end in [&edge.from, &edge.to] {
+                if !ids.contains_key(end.as_str()) {
+                    ids.insert(end, format!("n{}", ids.len()));
+                    implicit.push(end.as_str());
+                }
+            }
+        }
+
+        let mut res = String::from("flowchart LR\n");
+        let mut missing = vec![];
+        for (i, (cluster, nodes)) in self.clusters().into_iter().enumerate() {
+            let indent = match cluster {
+                Some(cluster) => {
+                    writeln!(res, "  subgraph c{} [{}]", i, quote_mermaid(cluster)).unwrap();
+                    "    "
+                }
+                None => "  ",
+            };
+            for node in nodes {
+                let id = &ids[node.id.as_str()];
+                writeln!(res, "{}{}[{}]", indent, id, quote_mermaid(&node.label)).unwrap();
+                if node.missing {
+                    missing.push(id.as_str());
+                }
+            }
+            if cluster.is_some() {
+                res.push_str("  end\n");
+            }
+        }
+        for end in implicit {
+            writeln!(res, "  {}[{}]", ids[end], quote_mermaid(end)).unwrap();
+        }
+        for edge in &self.edges {
+            let arrow = if edge.missing { "-.->" } else { "-->" };
+            let label = match &edge.label {
+                Some(label) => format!("|{}|", quote_mermaid(label)),
+                None => String::new(),
+            };
+            writeln!(
+                res,
+                "  {} {}{} {}",
+                ids[edge.from.as_str()],
+                arrow,
+                label,
+                ids[edge.to.as_str()]
+            )
+            .unwrap();
+        }
+        if !missing.is_empty() {
+            res.push_str("  classDef missing stroke:red,color:red,stroke-dasharray:5 5\n");
+            writeln!(res, "  class {} missing", missing.join(",")).unwrap();
+        }
+        res
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn render(&self, format: GraphFormat) -> anyhow::Result<String> {
+        match format {
+            GraphFormat::Dot => Ok(self.to_dot()),
+            GraphFormat::Mermaid => Ok(self.to_mermaid()),
+            GraphFormat::Json => self.to_json(),
+        }
+    }
+
+    /// Write to `path`, guessing the format from its extension if not given
+    pub fn save(&self, path: &Path, format: Option<GraphFormat>) -> anyhow::Result<()> {
+        let format = format.unwrap_or_else(|| GraphFormat::from_path(path));
+        std::fs::write(path, self.render(format)?)?;
+        Ok(())
+    }
+}
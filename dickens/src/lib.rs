@@ -1,6 +1,7 @@
+pub mod abbs;
+pub mod deb;
+pub mod depend;
+pub mod graph;
+pub mod publish;
 pub mod sodep;
 pub mod topic;
-
-pub fn escape_name_for_graphviz(name: &str) -> String {
-    name.replace("-", "_dash_").replace("+", "_plus_")
-}
@@ -0,0 +1,345 @@
+use log::info;
+use reqwest::{Client, ClientBuilder};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+/// Hidden marker to find the comment posted by dickens-topic
+pub const REPORT_MARKER: &str = "<!-- dickens-topic report -->";
+
+// GitHub rejects comments longer than this
+const MAX_COMMENT_LENGTH: usize = 65536;
+
+const TRUNCATED_NOTE: &str = "\n(report truncated)\n";
+const DETAILS_END: &str = "</details>\n";
+const FENCE_END: &str = "```\n";
+
+/// Cut `body` to at most `max` bytes at a line boundary, preferably between
+/// sections, closing code fences and `<details>` blocks left open
+fn truncate(body: &str, max: usize) -> String {
+    if body.len() <= max {
+        return body.to_string();
+    }
+
+    // (in a code fence, open <details> blocks) after the kept lines
+    let mut open = (false, 0);
+    let mut end = 0;
+    // end of the last line with no blocks open
+    let mut boundary = None;
+    for line in body.split_inclusive('\n') {
+        let (mut fence, mut details) = open;
+        if line.trim_start().starts_with("```") {
+            fence = !fence;
+        } else if !fence {
+            details += line.matches("<details").count();
+            details = details.saturating_sub(line.matches("</details>").count());
+        }
+
+        let closing = if fence { FENCE_END.len() } else { 0 } + details * DETAILS_END.len();
+        // a newline might be needed before the closing lines
+        if end + line.len() + 1 + closing + TRUNCATED_NOTE.len() > max {
+            break;
+        }
+        end += line.len();
+        open = (fence, details);
+        if !fence && details == 0 {
+            boundary = Some(end);
+        }
+    }
+
+    let mut res = match boundary {
+        Some(boundary) => body[..boundary].to_string(),
+        None => {
+            let mut res = body[..end].to_string();
+            if !res.is_empty() && !res.ends_with('\n') {
+                res.push('\n');
+            }
+            if open.0 {
+                res.push_str(FENCE_END);
+            }
+            res.push_str(&DETAILS_END.repeat(open.1));
+            res
+        }
+    };
+    res.push_str(TRUNCATED_NOTE);
+    res
+}
+
+pub trait Publisher {
+    /// Publish the report of a topic, replacing the previously published one if any
+    fn publish(&self, topic: &str, report: &str)
+        -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequest {
+    number: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Comment {
+    id: u64,
+    body: Option<String>,
+    /// `None` for deleted accounts
+    user: Option<User>,
+}
+
+#[derive(Debug, Serialize)]
+struct CommentBody<'a> {
+    body: &'a str,
+}
+
+/// Post reports as a comment to the pull request of the topic branch
+pub struct GitHubPublisher {
+    client: Client,
+    api_base: String,
+    repo: String,
+    token: String,
+}
+
+impl GitHubPublisher {
+    /// `api_base` is usually https://api.github.com, `repo` is in the form of `owner/name`
+    pub fn new(api_base: &str, repo: &str, token: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: ClientBuilder::new().user_agent("dickens").build()?,
+            api_base: api_base.trim_end_matches('/').to_string(),
+            repo: repo.to_string(),
+            token: token.to_string(),
+        })
+    }
+
+    /// Read the token from `GITHUB_TOKEN`
+    pub fn from_env(api_base: &str, repo: &str) -> anyhow::Result<Self> {
+        let Ok(token) = std::env::var("GITHUB_TOKEN") else {
+            anyhow::bail!("GITHUB_TOKEN is not set")
+        };
+        Self::new(api_base, repo, &token)
+    }
+
+    /// Request `path` of the API, relative to `api_base`
+    fn api_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}/{}", self.api_base, path))
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+    }
+
+    /// Request `path` of the repository
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.api_request(method, &format!("repos/{}/{}", self.repo, path))
+    }
+
+    /// Login of the user the token belongs to
+    async fn current_user(&self) -> anyhow::Result<String> {
+        let user: User = self
+            .api_request(reqwest::Method::GET, "user")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(user.login)
+    }
+
+    async fn find_pull_request(&self, topic: &str) -> anyhow::Result<u64> {
+        let owner = self.repo.split('/').next().unwrap_or_default();
+        let pulls: Vec<PullRequest> = self
+            .request(reqwest::Method::GET, "pulls")
+            .query(&[
+                ("head", format!("{owner}:{topic}")),
+                ("state", "open".into()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        match pulls.first() {
+            Some(pull) => Ok(pull.number),
+            None => anyhow::bail!("No open pull request found for topic {}", topic),
+        }
+    }
+
+    /// Find the report previously posted by `login`, ignoring comments of others
+    /// even if they carry the marker
+    async fn find_comment(&self, number: u64, login: &str) -> anyhow::Result<Option<u64>> {
+        let mut page = 1;
+        loop {
+            let comments: Vec<Comment> = self
+                .request(reqwest::Method::GET, &format!("issues/{number}/comments"))
+                .query(&[("per_page", 100), ("page", page)])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            if comments.is_empty() {
+                return Ok(None);
+            }
+
+            for comment in comments {
+                let mine = comment.user.is_some_and(|user| user.login == login);
+                if mine
+                    && comment
+                        .body
+                        .is_some_and(|body| body.starts_with(REPORT_MARKER))
+                {
+                    return Ok(Some(comment.id));
+                }
+            }
+            page += 1;
+        }
+    }
+}
+
+impl Publisher for GitHubPublisher {
+    async fn publish(&self, topic: &str, report: &str) -> anyhow::Result<()> {
+        let number = self.find_pull_request(topic).await?;
+
+        let body = truncate(&format!("{REPORT_MARKER}\n{report}"), MAX_COMMENT_LENGTH);
+
+        let login = self.current_user().await?;
+        let request = match self.find_comment(number, &login).await? {
+            Some(id) => {
+                info!("Updating comment {} of pull request #{}", id, number);
+                self.request(reqwest::Method::PATCH, &format!("issues/comments/{id}"))
+            }
+            None => {
+                info!("Creating comment on pull request #{}", number);
+                self.request(reqwest::Method::POST, &format!("issues/{number}/comments"))
+            }
+        };
+        request
+            .json(&CommentBody { body: &body })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+
+    const SECTION: &str = "<details>\n```diff\n+a\n```\n</details>\n";
+
+    #[test]
+    fn truncate_at_section_boundary() {
+        let body = SECTION.repeat(3);
+        let res = truncate(&body, SECTION.len() * 2 + TRUNCATED_NOTE.len() + 10);
+        assert_eq!(res, format!("{}{}", SECTION.repeat(2), TRUNCATED_NOTE));
+    }
+
+    #[test]
+    fn truncate_closes_open_blocks() {
+        let body = format!(
+            "<details>\n```diff\n{}```\n</details>\n",
+            "+line\n".repeat(100)
+        );
+        let res = truncate(&body, 200);
+        assert!(res.len() <= 200);
+        assert!(res.ends_with(&format!("+line\n```\n</details>\n{TRUNCATED_NOTE}")));
+    }
+
+    #[test]
+    fn truncate_keeps_short_body() {
+        assert_eq!(truncate(SECTION, SECTION.len()), SECTION);
+    }
+
+    async fn mock_pull_request(server: &mut Server, comments: &str) -> Vec<mockito::Mock> {
+        let user = server
+            .mock("GET", "/user")
+            .match_header("authorization", "Bearer token")
+            .with_body(r#"{"login": "bot"}"#)
+            .create_async()
+            .await;
+        let pulls = server
+            .mock("GET", "/repos/owner/repo/pulls")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("head".into(), "owner:topic".into()),
+                Matcher::UrlEncoded("state".into(), "open".into()),
+            ]))
+            .with_body(r#"[{"number": 1}]"#)
+            .create_async()
+            .await;
+        let first_page = server
+            .mock("GET", "/repos/owner/repo/issues/1/comments")
+            .match_query(Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_body(comments)
+            .create_async()
+            .await;
+        let second_page = server
+            .mock("GET", "/repos/owner/repo/issues/1/comments")
+            .match_query(Matcher::UrlEncoded("page".into(), "2".into()))
+            .with_body("[]")
+            .create_async()
+            .await;
+        vec![user, pulls, first_page, second_page]
+    }
+
+    fn expected_body() -> Matcher {
+        Matcher::Json(serde_json::json!({ "body": format!("{REPORT_MARKER}\nreport") }))
+    }
+
+    #[tokio::test]
+    async fn publish_creates_comment() {
+        let mut server = Server::new_async().await;
+        // a report planted by someone else is left alone
+        let comments = format!(
+            r#"[{{"id": 2, "body": "LGTM", "user": {{"login": "bot"}}}},
+                {{"id": 3, "body": "{REPORT_MARKER}\nfake", "user": {{"login": "someone"}}}},
+                {{"id": 4, "body": "{REPORT_MARKER}\nghost", "user": null}}]"#
+        );
+        let mocks = mock_pull_request(&mut server, &comments).await;
+        let create = server
+            .mock("POST", "/repos/owner/repo/issues/1/comments")
+            .match_body(expected_body())
+            .with_status(201)
+            .create_async()
+            .await;
+
+        GitHubPublisher::new(&server.url(), "owner/repo", "token")
+            .unwrap()
+            .publish("topic", "report")
+            .await
+            .unwrap();
+        for mock in mocks {
+            mock.assert_async().await;
+        }
+        create.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn publish_updates_comment() {
+        let mut server = Server::new_async().await;
+        let comments = format!(
+            r#"[{{"id": 2, "body": "LGTM", "user": {{"login": "someone"}}}},
+                {{"id": 3, "body": "{REPORT_MARKER}\nold", "user": {{"login": "bot"}}}}]"#
+        );
+        let _mocks = mock_pull_request(&mut server, &comments).await;
+        let update = server
+            .mock("PATCH", "/repos/owner/repo/issues/comments/3")
+            .match_body(expected_body())
+            .create_async()
+            .await;
+        let create = server
+            .mock("POST", "/repos/owner/repo/issues/1/comments")
+            .expect(0)
+            .create_async()
+            .await;
+
+        GitHubPublisher::new(&server.url(), "owner/repo", "token")
+            .unwrap()
+            .publish("topic", "report")
+            .await
+            .unwrap();
+        update.assert_async().await;
+        create.assert_async().await;
+    }
+}
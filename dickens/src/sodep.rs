@@ -1,97 +1,1096 @@
-use log::{debug, info};
-use std::{path::Path, process::Command};
+use crate::{deb::with_tarball, topic::download_pkg};
+use elf::{DynamicInfo, ElfArch, VersionNeed};
+use libaosc::packages::{Package, Packages};
+use log::{debug, info, warn};
+use reqwest::ClientBuilder;
+use serde::{Deserialize, Serialize};
+use solver::PackageVersion;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs::File,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::{mpsc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub mod check;
+pub mod elf;
+pub mod finding;
+pub mod index;
+pub mod provider;
+pub mod resolve;
+
+/// Installed path of a library, which may be a symlink, with its dynamic section
+pub type LibraryFile = (PathBuf, DynamicInfo);
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct LibraryDependency {
     pub name: String,
+    /// Installed path of the file
+    pub path: PathBuf,
+    pub arch: ElfArch,
     pub needed: Vec<String>,
+    pub rpath: Vec<String>,
+    pub runpath: Vec<String>,
+    /// Whether symbols are read, see `Scanner::with_symbols`
+    pub has_symbols: bool,
+    pub undefined_symbols: Vec<String>,
+    pub version_needs: Vec<VersionNeed>,
 }
 
-pub fn get_library_deps(name: &str) -> anyhow::Result<Vec<LibraryDependency>> {
-    info!("Handling package {}", name);
-    let mut res = vec![];
-    let output = Command::new("dpkg").arg("-L").arg(name).output()?;
-    if !output.status.success() {
-        anyhow::bail!("Failed to list files of package {}", name)
-    }
-
-    let contents = String::from_utf8(output.stdout)?;
-    for file in contents.lines() {
-        if file.starts_with("/usr/include/")
-            || file.starts_with("/usr/share/")
-            || file.starts_with("/etc/")
-            || file.starts_with("/usr/lib/pkgconfig/")
-            || file.starts_with("/usr/lib/gconv/")
-        {
+/// A NEEDED entry of a binary, none of whose symbols are used by it
+#[derive(Debug)]
+pub struct OverLinking {
+    pub binary: String,
+    pub arch: ElfArch,
+    pub needed: String,
+    /// Another NEEDED library of the binary which pulls it in anyway
+    pub via: Option<String>,
+}
+
+/// Whether `target` is in the shared library closure of `soname`
+fn reaches(soname: &str, target: &str, arch: &ElfArch, libraries: &SonameIndex) -> bool {
+    let mut visited = BTreeSet::from([soname]);
+    let mut todo = vec![soname];
+    while let Some(cur) = todo.pop() {
+        let Some(info) = libraries.info(cur, arch) else {
             continue;
+        };
+        for needed in &info.needed {
+            if needed == target {
+                return true;
+            }
+            if visited.insert(needed) {
+                todo.push(needed);
+            }
         }
+    }
+    false
+}
 
-        if Path::new(&file).is_symlink() {
+/// Compare undefined symbols of each binary against exported symbols of its NEEDED
+/// libraries, skipping files whose symbol tables are not read
+pub fn find_overlinking(deps: &[LibraryDependency], libraries: &SonameIndex) -> Vec<OverLinking> {
+    let mut res = vec![];
+    for dep in deps {
+        if !dep.has_symbols {
             continue;
         }
 
-        let readelf_result =
-            String::from_utf8(Command::new("readelf").arg("-d").arg(file).output()?.stdout)?;
+        for needed in &dep.needed {
+            // unresolved sonames are reported elsewhere
+            let Some(lib) = libraries.info(needed, &dep.arch) else {
+                continue;
+            };
+            if !lib.has_symbols {
+                continue;
+            }
 
-        let mut needed = vec![];
-        for line in readelf_result.lines() {
-            if line.contains("(NEEDED)") {
-                needed.push(line.split("[").last().unwrap().split("]").next().unwrap());
+            let exported: HashSet<&str> = lib.exported_symbols.iter().map(String::as_str).collect();
+            if dep
+                .undefined_symbols
+                .iter()
+                .any(|sym| exported.contains(sym.as_str()))
+            {
+                continue;
             }
-        }
 
-        if !needed.is_empty() {
-            res.push(LibraryDependency {
-                name: file.split("/").last().unwrap().to_string(),
-                needed: needed.into_iter().map(str::to_string).collect(),
+            let via = dep
+                .needed
+                .iter()
+                .filter(|other| *other != needed)
+                .find(|other| reaches(other, needed, &dep.arch, libraries))
+                .cloned();
+            res.push(OverLinking {
+                binary: dep.name.clone(),
+                arch: dep.arch,
+                needed: needed.clone(),
+                via,
             });
-            debug!("Found file {}", file);
         }
     }
+    res
+}
 
-    // dedup
-    res.sort();
-    res.dedup();
-    Ok(res)
+/// Split a symbol version like `GLIBC_2.2.5` into `GLIBC` and `[2, 2, 5]`,
+/// returns `None` for unnumbered ones like `GLIBC_PRIVATE`
+pub fn parse_symbol_version(version: &str) -> Option<(&str, Vec<u64>)> {
+    let (family, number) = version.rsplit_once('_')?;
+    let number = number
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    Some((family, number))
 }
 
-pub fn get_libraries(name: &str) -> anyhow::Result<Vec<String>> {
-    info!("Handling package {}", name);
-    let mut res: Vec<String> = vec![];
-    let output = Command::new("dpkg").arg("-L").arg(name).output()?;
-    if !output.status.success() {
-        anyhow::bail!("Failed to list files of package {}", name)
+/// The highest symbol version of a family a binary requires from a NEEDED library
+#[derive(Debug)]
+pub struct VersionRequirement {
+    pub binary: String,
+    pub arch: ElfArch,
+    pub needed: String,
+    pub version: String,
+    /// Whether the library defines this version, `None` if the library is not found
+    pub provided: Option<bool>,
+}
+
+/// Collect the highest required symbol versions of each binary and check them
+/// against .gnu.version_d of the libraries
+pub fn find_version_requirements(
+    deps: &[LibraryDependency],
+    libraries: &SonameIndex,
+) -> Vec<VersionRequirement> {
+    let mut res = vec![];
+    for dep in deps {
+        for need in &dep.version_needs {
+            // family => (number, version)
+            let mut highest: BTreeMap<&str, (Vec<u64>, &str)> = BTreeMap::new();
+            for version in &need.versions {
+                let Some((family, number)) = parse_symbol_version(version) else {
+                    continue;
+                };
+                if highest.get(family).is_none_or(|(cur, _)| *cur < number) {
+                    highest.insert(family, (number, version));
+                }
+            }
+
+            let lib = libraries.info(&need.file, &dep.arch);
+            for (_, version) in highest.into_values() {
+                res.push(VersionRequirement {
+                    binary: dep.name.clone(),
+                    arch: dep.arch,
+                    needed: need.file.clone(),
+                    version: version.to_string(),
+                    provided: lib.map(|lib| lib.version_defs.iter().any(|def| def == version)),
+                });
+            }
+        }
     }
+    res
+}
 
-    let contents = String::from_utf8(output.stdout)?;
-    for file in contents.lines() {
-        if !file.contains(".so") {
-            continue;
+/// Turn a symbol version into a versioned dependency if its family is named
+/// after the package, e.g. `GLIBC_2.38` of glibc into `glibc>=2.38`
+pub fn versioned_dependency(package: &str, version: &str) -> Option<String> {
+    let (family, number) = parse_symbol_version(version)?;
+    if !family.eq_ignore_ascii_case(package) {
+        return None;
+    }
+    let number: Vec<String> = number.iter().map(u64::to_string).collect();
+    Some(format!("{}>={}", package, number.join(".")))
+}
+
+/// A soname in the shared library closure of a binary
+#[derive(Debug)]
+pub struct ClosureNode {
+    pub soname: String,
+    /// Package providing the soname, `None` if missing
+    pub package: Option<String>,
+    pub children: Vec<ClosureNode>,
+    /// Already expanded elsewhere in the closure
+    pub repeated: bool,
+}
+
+impl ClosureNode {
+    /// Chains of sonames leading to a missing one, starting from this node
+    pub fn missing_chains(&self) -> Vec<Vec<&str>> {
+        if self.package.is_none() {
+            return vec![vec![&self.soname]];
+        }
+
+        let mut res = vec![];
+        for child in &self.children {
+            for mut chain in child.missing_chains() {
+                chain.insert(0, &self.soname);
+                res.push(chain);
+            }
+        }
+        res
+    }
+}
+
+/// Follow NEEDED entries recursively through libraries of known packages, only
+/// matching libraries compatible with `arch`
+pub fn library_closure(
+    needed: &[String],
+    arch: &ElfArch,
+    libraries: &SonameIndex,
+) -> Vec<ClosureNode> {
+    fn visit(
+        soname: &str,
+        arch: &ElfArch,
+        libraries: &SonameIndex,
+        visited: &mut BTreeSet<String>,
+    ) -> ClosureNode {
+        let repeated = !visited.insert(soname.to_string());
+        let mut children = vec![];
+        if !repeated {
+            if let Some(info) = libraries.info(soname, arch) {
+                for needed in &info.needed {
+                    children.push(visit(needed, arch, libraries, visited));
+                }
+            }
+        }
+        ClosureNode {
+            soname: soname.to_string(),
+            package: libraries.package(soname, arch).map(str::to_string),
+            children,
+            repeated,
+        }
+    }
+
+    let mut visited = BTreeSet::new();
+    needed
+        .iter()
+        .map(|soname| visit(soname, arch, libraries, &mut visited))
+        .collect()
+}
+
+/// Libraries of packages keyed by SONAME, with file names as aliases
+#[derive(Debug, Default)]
+pub struct SonameIndex<'a> {
+    /// Map soname => (package, dynamic section) for each architecture
+    libraries: BTreeMap<String, Vec<(&'a str, DynamicInfo)>>,
+    /// Map file name => soname, for symlinks and real files named differently
+    aliases: BTreeMap<String, String>,
+}
+
+impl<'a> SonameIndex<'a> {
+    /// Add a library installed at `path`, returns the package which provided the
+    /// same soname for a compatible architecture before
+    pub fn add(&mut self, package: &'a str, path: &Path, info: DynamicInfo) -> Option<&'a str> {
+        let name = file_name(path);
+        // libraries without SONAME are only found by file name
+        let soname = info.soname.clone().unwrap_or_else(|| name.clone());
+        if name != soname {
+            self.aliases.entry(name).or_insert_with(|| soname.clone());
         }
 
-        if file.starts_with("/usr/include/")
-            || file.starts_with("/usr/share/")
-            || file.starts_with("/etc/")
-            || file.starts_with("/usr/lib/pkgconfig/")
-            || file.starts_with("/usr/lib/gconv/")
+        let libraries = self.libraries.entry(soname).or_default();
+        match libraries
+            .iter_mut()
+            .find(|(_, lib)| lib.arch.is_compatible(&info.arch))
         {
-            continue;
+            Some(lib) => Some(std::mem::replace(lib, (package, info)).0),
+            None => {
+                libraries.push((package, info));
+                None
+            }
+        }
+    }
+
+    /// All libraries named `needed`, by SONAME first and then by file name
+    fn candidates(&self, needed: &str) -> &[(&'a str, DynamicInfo)] {
+        let libraries = match self.libraries.get(needed) {
+            Some(libraries) => Some(libraries),
+            None => self
+                .aliases
+                .get(needed)
+                .and_then(|soname| self.libraries.get(soname)),
+        };
+        libraries.map_or(&[], Vec::as_slice)
+    }
+
+    fn get(&self, needed: &str, arch: &ElfArch) -> Option<&(&'a str, DynamicInfo)> {
+        self.candidates(needed)
+            .iter()
+            .find(|(_, info)| arch.is_compatible(&info.arch))
+    }
+
+    /// Package providing `needed` for `arch`
+    pub fn package(&self, needed: &str, arch: &ElfArch) -> Option<&'a str> {
+        self.get(needed, arch).map(|(package, _)| *package)
+    }
+
+    pub fn info(&self, needed: &str, arch: &ElfArch) -> Option<&DynamicInfo> {
+        self.get(needed, arch).map(|(_, info)| info)
+    }
+
+    /// Packages providing `needed` only for other architectures
+    pub fn incompatible(&self, needed: &str, arch: &ElfArch) -> Vec<(&'a str, ElfArch)> {
+        self.candidates(needed)
+            .iter()
+            .filter(|(_, info)| !arch.is_compatible(&info.arch))
+            .map(|(package, info)| (*package, info.arch))
+            .collect()
+    }
+}
+
+/// Where to read the files of a package from
+#[derive(Debug, Clone)]
+pub enum PackageSource {
+    /// Package installed on this machine
+    Installed(String),
+    /// A .deb file, whose data tarball is read in memory
+    Deb { name: String, path: PathBuf },
+}
+
+impl PackageSource {
+    /// Use a .deb file, the package name is read from its control file
+    pub fn deb(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self::Deb {
+            name: crate::deb::package_name(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Treat arguments ending with .deb as files, and others as installed packages
+    pub fn from_arg(arg: &str) -> anyhow::Result<Self> {
+        if arg.ends_with(".deb") {
+            Self::deb(Path::new(arg))
+        } else {
+            Ok(Self::Installed(arg.to_string()))
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Installed(name) => name,
+            Self::Deb { name, .. } => name,
+        }
+    }
+}
+
+/// Whether a package is installed on this machine
+pub fn is_installed(name: &str) -> bool {
+    Command::new("dpkg-query")
+        .args(["-W", "-f", "${db:Status-Status}", name])
+        .output()
+        .is_ok_and(|output| output.status.success() && output.stdout == b"installed")
+}
+
+/// Latest versions of packages in a Packages index
+pub struct DebIndex {
+    path: PathBuf,
+    packages: BTreeMap<String, Package>,
+}
+
+impl DebIndex {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            packages: latest_versions(content.as_slice().try_into()?),
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.packages.contains_key(name)
+    }
+
+    /// Resolve package names to .deb files, downloading them to the local cache if
+    /// needed
+    pub async fn resolve(&self, names: &[String]) -> anyhow::Result<Vec<PackageSource>> {
+        let client = ClientBuilder::new().user_agent("dickens").build()?;
+
+        let mut res = vec![];
+        for name in names {
+            let Some(pkg) = self.packages.get(name) else {
+                anyhow::bail!("Package {} not found in {}", name, self.path.display())
+            };
+
+            res.push(PackageSource::Deb {
+                name: name.clone(),
+                path: download_pkg(&client, pkg).await?,
+            });
+        }
+        Ok(res)
+    }
+
+    /// Find .deb files of all packages under `dir`, without downloading, returns
+    /// names of packages not found as well
+    pub fn local_debs(&self, dir: &Path) -> (Vec<PackageSource>, Vec<String>) {
+        let mut found = vec![];
+        let mut missing = vec![];
+        for (name, pkg) in &self.packages {
+            // pool/stable/main/f/foo_1.0_amd64.deb
+            let path = dir.join(Path::new(&pkg.filename).file_name().unwrap_or_default());
+            if path.is_file() {
+                found.push(PackageSource::Deb {
+                    name: name.clone(),
+                    path,
+                });
+            } else {
+                missing.push(name.clone());
+            }
+        }
+        (found, missing)
+    }
+}
+
+/// Map package names to their latest versions in the index
+fn latest_versions(packages: Packages) -> BTreeMap<String, Package> {
+    let mut res: BTreeMap<String, Package> = BTreeMap::new();
+    for pkg in packages.0 {
+        let latest = res.get(&pkg.package);
+        if latest.is_none_or(|latest| {
+            PackageVersion::from(&latest.version).ok() < PackageVersion::from(&pkg.version).ok()
+        }) {
+            res.insert(pkg.package.clone(), pkg);
+        }
+    }
+    res
+}
+
+#[derive(Debug)]
+enum FileContent {
+    /// Regular file on the local filesystem
+    OnDisk(PathBuf),
+    /// Regular file read from a .deb
+    InMemory { data: Vec<u8>, mtime: u64 },
+    /// Symlink or hard link to another path
+    Link(PathBuf),
+}
+
+#[derive(Debug)]
+struct PackageFile {
+    path: PathBuf,
+    content: FileContent,
+    /// Unique key in the scan cache
+    key: String,
+}
+
+fn is_ignored(file: &str) -> bool {
+    file.starts_with("/usr/include/")
+        || file.starts_with("/usr/share/")
+        || file.starts_with("/etc/")
+        || file.starts_with("/usr/lib/pkgconfig/")
+        || file.starts_with("/usr/lib/gconv/")
+}
+
+/// Call `f` on each file of a package in order, members of a .deb are read one
+/// at a time so that only ELF files being handled by `f` are kept in memory
+fn for_each_file(
+    source: &PackageSource,
+    mut f: impl FnMut(PackageFile) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    info!("Handling package {}", source.name());
+    match source {
+        PackageSource::Installed(name) => {
+            let output = Command::new("dpkg").arg("-L").arg(name).output()?;
+            if !output.status.success() {
+                anyhow::bail!("Failed to list files of package {}", name)
+            }
+
+            let contents = String::from_utf8(output.stdout)?;
+            for file in contents.lines() {
+                if is_ignored(file) {
+                    continue;
+                }
+
+                let path = PathBuf::from(file);
+                let content = if path.is_symlink() {
+                    FileContent::Link(std::fs::read_link(&path)?)
+                } else if path.is_file() {
+                    FileContent::OnDisk(path.clone())
+                } else {
+                    continue;
+                };
+                f(PackageFile {
+                    path,
+                    content,
+                    key: file.to_string(),
+                })?;
+            }
+        }
+        PackageSource::Deb { path: deb, .. } => {
+            with_tarball(deb, "data", |tarball| {
+                for entry in tarball.entries()? {
+                    let mut entry = entry?;
+                    let path = installed_path(&entry.path()?);
+                    if is_ignored(&path.to_string_lossy()) {
+                        continue;
+                    }
+
+                    let entry_type = entry.header().entry_type();
+                    let content = if entry_type.is_symlink() || entry_type.is_hard_link() {
+                        let Some(target) = entry.link_name()? else {
+                            continue;
+                        };
+                        if entry_type.is_hard_link() {
+                            FileContent::Link(installed_path(&target))
+                        } else {
+                            FileContent::Link(target.to_path_buf())
+                        }
+                    } else if entry_type.is_file() {
+                        let mut data = vec![];
+                        entry.read_to_end(&mut data)?;
+                        // only keep ELF files in memory
+                        if !elf::is_elf(&data) {
+                            continue;
+                        }
+                        FileContent::InMemory {
+                            data,
+                            mtime: entry.header().mtime()?,
+                        }
+                    } else {
+                        continue;
+                    };
+                    f(PackageFile {
+                        key: format!("{}:{}", deb.display(), path.display()),
+                        path,
+                        content,
+                    })?;
+                }
+                Ok(())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Path of a tarball member once installed, `./usr/lib/libfoo.so` or
+/// `usr/lib/libfoo.so` => `/usr/lib/libfoo.so`
+fn installed_path(member: &Path) -> PathBuf {
+    Path::new("/").join(member.strip_prefix(".").unwrap_or(member))
+}
+
+/// Resolve `.` and `..` lexically, as symlink targets within a package cannot be
+/// followed on the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                res.pop();
+            }
+            component => res.push(component),
         }
+    }
+    res
+}
 
-        let readelf_result =
-            String::from_utf8(Command::new("readelf").arg("-d").arg(file).output()?.stdout)?;
+/// Follow links within the package, returning the regular file they point to
+fn resolve_link<'a>(
+    files: &BTreeMap<&Path, &'a ScannedFile>,
+    file: &'a ScannedFile,
+) -> Option<&'a ScannedFile> {
+    let mut cur = file;
+    // avoid loops
+    for _ in 0..16 {
+        match &cur.link {
+            Some(target) => {
+                let target = normalize(&cur.path.parent()?.join(target));
+                cur = files.get(target.as_path())?;
+            }
+            None => return Some(cur),
+        }
+    }
+    None
+}
+
+/// Parse the dynamic section of a regular file, skipping non-ELF files
+fn read_dynamic(file: &PackageFile) -> anyhow::Result<Option<DynamicInfo>> {
+    let res = match &file.content {
+        FileContent::OnDisk(path) => {
+            let mut magic = [0u8; 4];
+            if File::open(path)?.read_exact(&mut magic).is_err() || !elf::is_elf(&magic) {
+                return Ok(None);
+            }
+            elf::parse(&std::fs::read(path)?)
+        }
+        FileContent::InMemory { data, .. } => elf::parse(data),
+        FileContent::Link(_) => anyhow::bail!("{} is a link", file.path.display()),
+    };
 
-        for line in readelf_result.lines() {
-            if line.contains("(SONAME)") || line.contains("(NEEDED)") {
-                res.push(file.split("/").last().unwrap().to_string());
-                debug!("Found file {}", file);
-                break;
+    match res {
+        Ok(res) => {
+            for err in res.iter().flat_map(|info| &info.errors) {
+                warn!("Failed to read {}: {}", file.path.display(), err);
             }
+            Ok(res)
+        }
+        Err(err) => {
+            warn!("Failed to parse ELF {}: {}", file.path.display(), err);
+            Ok(None)
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// A file of a package along with its parsed dynamic section
+#[derive(Debug)]
+struct ScannedFile {
+    path: PathBuf,
+    /// Target of symlinks and hard links
+    link: Option<PathBuf>,
+    info: Option<DynamicInfo>,
+}
+
+impl ScannedFile {
+    fn new(file: PackageFile, info: Option<DynamicInfo>) -> Self {
+        Self {
+            path: file.path,
+            link: match file.content {
+                FileContent::Link(target) => Some(target),
+                _ => None,
+            },
+            info,
+        }
+    }
+}
+
+/// Bumped whenever the layout of cached results changes, to discard old caches
+const CACHE_VERSION: u32 = 1;
+
+/// Cached results not used for this long are dropped when saving
+const CACHE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    mtime: u64,
+    size: u64,
+    /// Seconds since the epoch when this entry was last used
+    used: u64,
+    info: Option<DynamicInfo>,
+}
+
+/// On-disk layout of the cache, with `T` a map of file key => entry
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile<T> {
+    version: u32,
+    entries: T,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// Scan files of packages concurrently, caching results by path, mtime and size
+pub struct Scanner {
+    jobs: usize,
+    /// Whether symbols are needed, which are not cached
+    symbols: bool,
+    cache_path: Option<PathBuf>,
+    cache: Mutex<BTreeMap<String, CacheEntry>>,
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, |jobs| jobs.get()))
+    }
+}
+
+impl Scanner {
+    pub fn new(jobs: usize) -> Self {
+        Self {
+            jobs: jobs.max(1),
+            symbols: false,
+            cache_path: None,
+            cache: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Read dynamic symbols of files as well, bypassing cached results which have
+    /// no symbols
+    pub fn with_symbols(mut self) -> Self {
+        self.symbols = true;
+        self
+    }
+
+    /// Load cached results from `path`, which is written back by `save_cache`
+    pub fn with_cache(mut self, path: &Path) -> anyhow::Result<Self> {
+        if path.exists() {
+            match serde_json::from_slice::<CacheFile<_>>(&std::fs::read(path)?) {
+                Ok(cache) if cache.version == CACHE_VERSION => {
+                    self.cache = Mutex::new(cache.entries)
+                }
+                Ok(cache) => info!(
+                    "Ignoring cache {} of version {}",
+                    path.display(),
+                    cache.version
+                ),
+                Err(err) => warn!(
+                    "Ignoring broken or outdated cache {}: {}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+        self.cache_path = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    /// Write the cache back, dropping entries unused for `CACHE_MAX_AGE`
+    pub fn save_cache(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.cache_path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut entries = self.cache.lock().unwrap();
+        let expired = now().saturating_sub(CACHE_MAX_AGE.as_secs());
+        entries.retain(|_, entry| entry.used >= expired);
+        let cache = CacheFile {
+            version: CACHE_VERSION,
+            entries: &*entries,
+        };
+
+        // write to a temporary file first, so that concurrent runs never read a
+        // partially written cache
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{}.tmp", std::process::id()));
+        std::fs::write(&tmp, serde_json::to_vec(&cache)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn scan_file(&self, file: &PackageFile) -> anyhow::Result<Option<DynamicInfo>> {
+        let (mtime, size) = match &file.content {
+            FileContent::OnDisk(path) => {
+                let meta = std::fs::metadata(path)?;
+                let mtime = meta.modified()?.duration_since(UNIX_EPOCH)?;
+                (mtime.as_nanos() as u64, meta.len())
+            }
+            FileContent::InMemory { data, mtime } => (*mtime, data.len() as u64),
+            FileContent::Link(_) => return Ok(None),
+        };
+
+        if let Some(entry) = self.cache.lock().unwrap().get_mut(&file.key) {
+            if entry.mtime == mtime && entry.size == size && !self.symbols {
+                entry.used = now();
+                return Ok(entry.info.clone());
+            }
+        }
+
+        let info = read_dynamic(file)?;
+        self.cache.lock().unwrap().insert(
+            file.key.clone(),
+            CacheEntry {
+                mtime,
+                size,
+                used: now(),
+                info: info.clone(),
+            },
+        );
+        Ok(info)
+    }
+
+    fn scan_package(&self, source: &PackageSource) -> anyhow::Result<Vec<ScannedFile>> {
+        // bounded worker pool fed while the package is read, so that at most a
+        // few files are in memory at once, and each is dropped once scanned
+        let (sender, receiver) = mpsc::sync_channel::<(usize, PackageFile)>(self.jobs);
+        let receiver = Mutex::new(receiver);
+        let (listed, results) = std::thread::scope(|s| {
+            let workers: Vec<_> = (0..self.jobs)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut res = vec![];
+                        loop {
+                            // release the lock before scanning
+                            let next = receiver.lock().unwrap().recv();
+                            let Ok((index, file)) = next else {
+                                break;
+                            };
+                            let info = self.scan_file(&file);
+                            res.push((index, info.map(|info| ScannedFile::new(file, info))));
+                        }
+                        res
+                    })
+                })
+                .collect();
+
+            let mut count = 0;
+            let listed = for_each_file(source, |file| {
+                sender
+                    .send((count, file))
+                    .map_err(|_| anyhow::anyhow!("Scan workers exited early"))?;
+                count += 1;
+                Ok(())
+            });
+            drop(sender);
+
+            let results: Vec<(usize, anyhow::Result<ScannedFile>)> = workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("scan worker panicked"))
+                .collect();
+            (listed, results)
+        });
+        listed?;
+
+        let mut files: Vec<(usize, ScannedFile)> = results
+            .into_iter()
+            .map(|(index, file)| Ok((index, file?)))
+            .collect::<anyhow::Result<_>>()?;
+        files.sort_by_key(|(index, _)| *index);
+        Ok(files.into_iter().map(|(_, file)| file).collect())
+    }
+
+    /// Installed paths of libraries, including symlinks, with their dynamic sections
+    pub fn get_library_files(&self, source: &PackageSource) -> anyhow::Result<Vec<LibraryFile>> {
+        Ok(library_files(&self.scan_package(source)?))
+    }
+
+    /// Both `get_library_files` and files with NEEDED entries, reading the package
+    /// once
+    pub fn get_library_files_and_deps(
+        &self,
+        source: &PackageSource,
+    ) -> anyhow::Result<(Vec<LibraryFile>, Vec<LibraryDependency>)> {
+        let files = self.scan_package(source)?;
+        Ok((library_files(&files), library_deps(&files)))
+    }
+}
+
+fn library_deps(files: &[ScannedFile]) -> Vec<LibraryDependency> {
+    let mut res = vec![];
+    for file in files {
+        let Some(info) = &file.info else {
+            continue;
+        };
+
+        if !info.needed.is_empty() {
+            debug!("Found file {}", file.path.display());
+            res.push(LibraryDependency {
+                name: file_name(&file.path),
+                path: file.path.clone(),
+                arch: info.arch,
+                needed: info.needed.clone(),
+                rpath: info.rpath.clone(),
+                runpath: info.runpath.clone(),
+                has_symbols: info.has_symbols,
+                undefined_symbols: info.undefined_symbols.clone(),
+                version_needs: info.version_needs.clone(),
+            });
         }
     }
 
     // dedup
     res.sort();
     res.dedup();
-    Ok(res)
+    res
+}
+
+fn library_files(files: &[ScannedFile]) -> Vec<LibraryFile> {
+    let mut res = vec![];
+    let by_path: BTreeMap<&Path, &ScannedFile> = files
+        .iter()
+        .map(|file| (file.path.as_path(), file))
+        .collect();
+    for file in files {
+        let Some(info) = resolve_link(&by_path, file).and_then(|target| target.info.as_ref())
+        else {
+            continue;
+        };
+
+        if info.is_library() {
+            res.push((file.path.clone(), info.clone()));
+            debug!("Found file {}", file.path.display());
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installed_path_with_or_without_dot() {
+        for member in ["./usr/lib/libfoo.so", "usr/lib/libfoo.so"] {
+            assert_eq!(
+                installed_path(Path::new(member)),
+                Path::new("/usr/lib/libfoo.so")
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_link_with_parent_dir() {
+        let link = ScannedFile {
+            path: "/usr/lib/libfoo.so.1".into(),
+            link: Some("../lib64/./libfoo.so.1.0".into()),
+            info: None,
+        };
+        let target = ScannedFile {
+            path: "/usr/lib64/libfoo.so.1.0".into(),
+            link: None,
+            info: None,
+        };
+        let files = BTreeMap::from([
+            (link.path.as_path(), &link),
+            (target.path.as_path(), &target),
+        ]);
+        assert_eq!(
+            resolve_link(&files, &link).map(|file| &file.path),
+            Some(&target.path)
+        );
+    }
+
+    const X86_64: ElfArch = ElfArch {
+        class: elf::ElfClass::Elf64,
+        machine: 62,
+        osabi: 0,
+    };
+
+    fn library(soname: &str, needed: &[&str], exported: &[&str]) -> DynamicInfo {
+        DynamicInfo {
+            arch: X86_64,
+            elf_type: 3,
+            interpreter: None,
+            soname: Some(soname.to_string()),
+            needed: needed.iter().map(|s| s.to_string()).collect(),
+            rpath: vec![],
+            runpath: vec![],
+            flags: 0,
+            flags_1: 0,
+            has_symbols: true,
+            exported_symbols: exported.iter().map(|s| s.to_string()).collect(),
+            undefined_symbols: vec![],
+            version_needs: vec![],
+            version_defs: vec![],
+            errors: vec![],
+        }
+    }
+
+    fn binary(needed: &[&str], undefined: &[&str], has_symbols: bool) -> LibraryDependency {
+        LibraryDependency {
+            name: "app".to_string(),
+            path: "/usr/bin/app".into(),
+            arch: X86_64,
+            needed: needed.iter().map(|s| s.to_string()).collect(),
+            rpath: vec![],
+            runpath: vec![],
+            has_symbols,
+            undefined_symbols: undefined.iter().map(|s| s.to_string()).collect(),
+            version_needs: vec![],
+        }
+    }
+
+    #[test]
+    fn overlinking_via_closure() {
+        let mut libraries = SonameIndex::default();
+        // libtop.so.1 => libmid.so.1 => libbase.so.1
+        for (soname, needed, exported) in [
+            ("libtop.so.1", &["libmid.so.1"][..], &["top"][..]),
+            ("libmid.so.1", &["libbase.so.1"], &["mid"]),
+            ("libbase.so.1", &[], &["base"]),
+            ("libextra.so.1", &[], &["extra"]),
+        ] {
+            let path = Path::new("/usr/lib").join(soname);
+            libraries.add("pkg", &path, library(soname, needed, exported));
+        }
+
+        let deps = [binary(
+            &["libtop.so.1", "libbase.so.1", "libextra.so.1"],
+            &["top", "printf"],
+            true,
+        )];
+        let res = find_overlinking(&deps, &libraries);
+        let res: Vec<_> = res
+            .iter()
+            .map(|item| (item.needed.as_str(), item.via.as_deref()))
+            .collect();
+        assert_eq!(
+            res,
+            [
+                ("libbase.so.1", Some("libtop.so.1")),
+                ("libextra.so.1", None)
+            ]
+        );
+
+        // without symbols every NEEDED entry would look unused
+        let deps = [binary(&["libtop.so.1"], &[], false)];
+        assert!(find_overlinking(&deps, &libraries).is_empty());
+    }
+
+    /// An in-memory file that is not ELF, so that a cache miss yields `None`
+    fn cached_file(key: &str, mtime: u64) -> PackageFile {
+        PackageFile {
+            path: "/usr/lib/libfoo.so.1".into(),
+            content: FileContent::InMemory {
+                data: b"junk".to_vec(),
+                mtime,
+            },
+            key: key.to_string(),
+        }
+    }
+
+    fn cache_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dickens-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn cache_round_trip_and_expiry() {
+        let path = cache_path("cache");
+        let info = library("libfoo.so.1", &["libc.so.6"], &[]);
+        let scanner = Scanner::new(1).with_cache(&path).unwrap();
+        for (key, used) in [("fresh", now()), ("stale", 0)] {
+            scanner.cache.lock().unwrap().insert(
+                key.to_string(),
+                CacheEntry {
+                    mtime: 1,
+                    size: 4,
+                    used,
+                    info: Some(info.clone()),
+                },
+            );
+        }
+        scanner.save_cache().unwrap();
+
+        let scanner = Scanner::new(1).with_cache(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let cached = scanner
+            .scan_file(&cached_file("fresh", 1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached.soname.as_deref(), Some("libfoo.so.1"));
+        assert_eq!(cached.needed, ["libc.so.6"]);
+        // symbols are not cached
+        assert!(!cached.has_symbols);
+
+        // entries unused for too long are dropped on save
+        assert!(scanner
+            .scan_file(&cached_file("stale", 1))
+            .unwrap()
+            .is_none());
+        // a modified file is scanned again, replacing the entry
+        assert!(scanner
+            .scan_file(&cached_file("fresh", 2))
+            .unwrap()
+            .is_none());
+        assert!(scanner
+            .scan_file(&cached_file("fresh", 1))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn cache_of_other_version_is_ignored() {
+        let path = cache_path("cache-version");
+        let entries = BTreeMap::from([(
+            "fresh".to_string(),
+            CacheEntry {
+                mtime: 1,
+                size: 4,
+                used: now(),
+                info: Some(library("libfoo.so.1", &[], &[])),
+            },
+        )]);
+        let cache = CacheFile {
+            version: CACHE_VERSION + 1,
+            entries,
+        };
+        std::fs::write(&path, serde_json::to_vec(&cache).unwrap()).unwrap();
+
+        let scanner = Scanner::new(1).with_cache(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(scanner
+            .scan_file(&cached_file("fresh", 1))
+            .unwrap()
+            .is_none());
+    }
 }
@@ -0,0 +1,639 @@
+//! Checks of a package against libraries of its dependencies, each returning
+//! findings
+
+use super::{
+    file_name, find_overlinking,
+    finding::{Finding, Kind},
+    parse_symbol_version,
+    provider::ProviderIndex,
+    resolve::{Resolution, Resolver, SearchPath},
+    versioned_dependency, ClosureNode, LibraryDependency, LibraryFile, SonameIndex,
+    VersionRequirement,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+/// The checked package and what it may depend on without declaring
+pub struct Context<'a> {
+    /// Name of the checked package
+    pub package: &'a str,
+    /// Packages assumed to be always available
+    pub builtins: &'a BTreeSet<String>,
+    /// Runtime dependencies declared in PKGDEP, empty without an ABBS tree
+    pub declared: &'a BTreeSet<String>,
+}
+
+impl Context<'_> {
+    /// The package itself or a builtin one, which never need declaring
+    fn is_implied(&self, pkg: &str) -> bool {
+        pkg == self.package || self.builtins.contains(pkg)
+    }
+}
+
+/// Index libraries of packages by soname and by path, reporting sonames shipped
+/// by more than one package
+pub fn index_libraries<'a>(
+    sources: &[(&'a str, Vec<LibraryFile>)],
+    resolver: &mut Resolver<'a>,
+) -> (SonameIndex<'a>, Vec<Finding>) {
+    let mut libraries = SonameIndex::default();
+    let mut findings = vec![];
+    for (pkg, files) in sources {
+        for (path, info) in files {
+            resolver.add_file(pkg, path, info.soname.as_deref(), info.arch);
+            let lib = info.soname.clone().unwrap_or_else(|| file_name(path));
+            if let Some(prev) = libraries.add(pkg, path, info.clone()) {
+                if prev != *pkg {
+                    findings.push(
+                        Finding::new(
+                            Kind::DuplicateProvider,
+                            format!("{lib} appears in both {prev} and {pkg}"),
+                        )
+                        .soname(&lib)
+                        .package(pkg),
+                    );
+                }
+            }
+        }
+    }
+    (libraries, findings)
+}
+
+/// How NEEDED entries of a package are resolved
+#[derive(Debug, Default)]
+pub struct Resolved<'a> {
+    /// Packages providing a NEEDED library in the search path
+    pub depended: BTreeSet<&'a str>,
+    /// NEEDED sonames not found in the search path
+    pub missing: BTreeSet<String>,
+    /// Each NEEDED entry as (file name, soname, resolution)
+    pub entries: Vec<(String, String, Option<Resolution<'a>>)>,
+    pub findings: Vec<Finding>,
+}
+
+/// Resolve NEEDED entries of `deps` like the loader, reporting those missing,
+/// outside the search path or only available for other architectures
+pub fn resolve_needed<'a>(
+    deps: &[LibraryDependency],
+    resolver: &Resolver<'a>,
+    libraries: &SonameIndex<'a>,
+) -> Resolved<'a> {
+    let mut res = Resolved::default();
+    for lib in deps {
+        for needed in &lib.needed {
+            let resolution =
+                resolver.resolve(&lib.path, &lib.rpath, &lib.runpath, needed, &lib.arch);
+            match &resolution {
+                Some(resolution) if resolution.via == SearchPath::NameOnly => {
+                    res.findings.push(
+                        Finding::new(
+                            Kind::UnsearchedSoname,
+                            format!(
+                                "Library/executable {} missing dependency {}, found at {} in {} which is not in its library search path",
+                                lib.name,
+                                needed,
+                                resolution.path.display(),
+                                resolution.package
+                            ),
+                        )
+                        .file(&lib.name)
+                        .soname(needed)
+                        .package(resolution.package),
+                    );
+                    res.missing.insert(needed.clone());
+                }
+                Some(resolution) => {
+                    res.depended.insert(resolution.package);
+                }
+                None if needed.contains('/') && Path::new(needed).is_relative() => {
+                    res.findings.push(
+                        Finding::new(
+                            Kind::MissingSoname,
+                            format!(
+                                "Library/executable {} needs {} by a relative path, which the loader looks up from the working directory",
+                                lib.name, needed
+                            ),
+                        )
+                        .file(&lib.name)
+                        .soname(needed),
+                    );
+                }
+                None => {
+                    let incompatible = libraries.incompatible(needed, &lib.arch);
+                    let finding = if incompatible.is_empty() {
+                        Finding::new(
+                            Kind::MissingSoname,
+                            format!(
+                                "Library/executable {} missing dependency {}",
+                                lib.name, needed
+                            ),
+                        )
+                    } else {
+                        let providers: Vec<String> = incompatible
+                            .iter()
+                            .map(|(pkg, arch)| format!("{arch} provider {pkg}"))
+                            .collect();
+                        Finding::new(
+                            Kind::ArchMismatch,
+                            format!(
+                                "{} {} needs {}, only {} available",
+                                lib.arch,
+                                lib.name,
+                                needed,
+                                providers.join(", ")
+                            ),
+                        )
+                        .package(incompatible[0].0)
+                    };
+                    res.findings.push(finding.file(&lib.name).soname(needed));
+                    res.missing.insert(needed.clone());
+                }
+            }
+            res.entries
+                .push((lib.name.clone(), needed.clone(), resolution));
+        }
+    }
+    res
+}
+
+/// Report symbol versions required from libraries which do not define them
+pub fn missing_symbol_versions(reqs: &[VersionRequirement]) -> Vec<Finding> {
+    reqs.iter()
+        .filter(|req| req.provided == Some(false))
+        .map(|req| {
+            Finding::new(
+                Kind::MissingSymbolVersion,
+                format!(
+                    "Library/executable {} requires symbol version {} which {} does not define",
+                    req.binary, req.version, req.needed
+                ),
+            )
+            .file(&req.binary)
+            .soname(&req.needed)
+        })
+        .collect()
+}
+
+/// Map package => versioned dependency on it, from the highest symbol version
+/// required from its libraries
+pub fn versioned_dependencies<'a>(
+    reqs: &[VersionRequirement],
+    libraries: &SonameIndex<'a>,
+) -> BTreeMap<&'a str, String> {
+    let mut res: BTreeMap<&str, (Vec<u64>, String)> = BTreeMap::new();
+    for req in reqs {
+        let Some(pkg) = libraries.package(&req.needed, &req.arch) else {
+            continue;
+        };
+        let (Some(dep), Some((_, number))) = (
+            versioned_dependency(pkg, &req.version),
+            parse_symbol_version(&req.version),
+        ) else {
+            continue;
+        };
+        if res.get(pkg).is_none_or(|(cur, _)| *cur < number) {
+            res.insert(pkg, (number, dep));
+        }
+    }
+    res.into_iter().map(|(pkg, (_, dep))| (pkg, dep)).collect()
+}
+
+/// Report dependencies none of whose libraries are NEEDED
+pub fn unused_dependencies(
+    ctx: &Context,
+    depends: &[String],
+    depended: &BTreeSet<&str>,
+) -> Vec<Finding> {
+    let mut res = vec![];
+    for pkg in depends {
+        if depended.contains(pkg.as_str()) || ctx.builtins.contains(pkg) {
+            continue;
+        }
+        let message = if ctx.declared.contains(pkg) {
+            format!(
+                "Package {} is declared in PKGDEP but not depended by {}",
+                pkg, ctx.package
+            )
+        } else {
+            format!("Package {} is not depended by {}", pkg, ctx.package)
+        };
+        res.push(Finding::new(Kind::UnusedDependency, message).package(pkg));
+    }
+    res
+}
+
+/// Report sonames missing deeper in the library closure of `binary`. Direct ones
+/// are reported by `resolve_needed`, and only sonames no package provides at all
+/// count as missing
+pub fn transitive_missing(
+    binary: &str,
+    closure: &[ClosureNode],
+    unprovided: &BTreeSet<String>,
+) -> Vec<Finding> {
+    let chains: BTreeSet<Vec<&str>> = closure
+        .iter()
+        .flat_map(ClosureNode::missing_chains)
+        .filter(|chain| chain.len() > 1 && unprovided.contains(chain[chain.len() - 1]))
+        .collect();
+    chains
+        .into_iter()
+        .map(|chain| {
+            let soname = chain[chain.len() - 1];
+            Finding::new(
+                Kind::TransitiveMissingSoname,
+                format!(
+                    "Library/executable {} missing dependency {} through {} -> {}",
+                    binary,
+                    soname,
+                    binary,
+                    chain.join(" -> ")
+                ),
+            )
+            .file(binary)
+            .soname(soname)
+        })
+        .collect()
+}
+
+/// Report NEEDED libraries whose symbols are never used, and depended packages
+/// only reachable through other libraries or not used at all
+pub fn overlinking(
+    ctx: &Context,
+    deps: &[LibraryDependency],
+    libraries: &SonameIndex,
+    depended: &BTreeSet<&str>,
+) -> Vec<Finding> {
+    let overlinked = find_overlinking(deps, libraries);
+    let mut res = vec![];
+    for item in &overlinked {
+        let message = match &item.via {
+            Some(via) => format!(
+                "{} links to {} without using its symbols, it is already pulled in by {}",
+                item.binary, item.needed, via
+            ),
+            None => format!(
+                "{} links to {} without using any of its symbols",
+                item.binary, item.needed
+            ),
+        };
+        res.push(
+            Finding::new(Kind::OverLinking, message)
+                .file(&item.binary)
+                .soname(&item.needed),
+        );
+    }
+
+    // packages with at least one NEEDED library whose symbols are used
+    let mut used: BTreeSet<&str> = BTreeSet::new();
+    for lib in deps {
+        for needed in &lib.needed {
+            if !overlinked
+                .iter()
+                .any(|item| item.binary == lib.name && &item.needed == needed)
+            {
+                if let Some(pkg) = libraries.package(needed, &lib.arch) {
+                    used.insert(pkg);
+                }
+            }
+        }
+    }
+
+    for pkg in depended {
+        if used.contains(pkg) || ctx.is_implied(pkg) {
+            continue;
+        }
+
+        let items: Vec<_> = overlinked
+            .iter()
+            .filter(|item| libraries.package(&item.needed, &item.arch) == Some(*pkg))
+            .collect();
+        if items.iter().all(|item| item.via.is_some()) {
+            let vias: BTreeSet<&str> = items
+                .iter()
+                .filter_map(|item| item.via.as_deref())
+                .collect();
+            res.push(
+                Finding::new(
+                    Kind::TransitiveDependency,
+                    format!(
+                        "Package {} is only reachable transitively through {}",
+                        pkg,
+                        vias.into_iter().collect::<Vec<_>>().join(", ")
+                    ),
+                )
+                .package(pkg),
+            );
+        } else {
+            res.push(
+                Finding::new(
+                    Kind::UnusedSymbols,
+                    format!("Package {} is linked but none of its symbols are used", pkg),
+                )
+                .package(pkg),
+            );
+        }
+    }
+    res
+}
+
+/// Report depended packages and providers of missing sonames not in PKGDEP
+pub fn undeclared_dependencies(
+    ctx: &Context,
+    depended: &BTreeSet<&str>,
+    missing: &BTreeSet<String>,
+    providers: &ProviderIndex,
+) -> anyhow::Result<Vec<Finding>> {
+    let mut res = vec![];
+    for pkg in depended {
+        if !ctx.is_implied(pkg) && !ctx.declared.contains(*pkg) {
+            res.push(
+                Finding::new(
+                    Kind::UndeclaredDependency,
+                    format!(
+                        "Package {} is depended by {} but not in PKGDEP",
+                        pkg, ctx.package
+                    ),
+                )
+                .package(pkg),
+            );
+        }
+    }
+
+    for soname in missing {
+        let candidates = providers.find(soname)?;
+        if candidates.is_empty()
+            || candidates
+                .iter()
+                .any(|pkg| ctx.is_implied(pkg) || ctx.declared.contains(pkg))
+        {
+            continue;
+        }
+        let mut finding = Finding::new(
+            Kind::UndeclaredDependency,
+            format!(
+                "{} needs {} from {}, which is not in PKGDEP",
+                ctx.package,
+                soname,
+                candidates.join(" or ")
+            ),
+        )
+        .soname(soname);
+        if let [provider] = candidates.as_slice() {
+            finding = finding.package(provider);
+        }
+        res.push(finding);
+    }
+    Ok(res)
+}
+
+/// Suggest PKGDEP from depended packages and providers of missing sonames, with
+/// versioned dependencies where known
+pub fn suggest_pkgdep(
+    ctx: &Context,
+    depended: &BTreeSet<&str>,
+    missing: &BTreeSet<String>,
+    versioned: &BTreeMap<&str, String>,
+    providers: &ProviderIndex,
+) -> anyhow::Result<(Vec<String>, Vec<Finding>)> {
+    let mut findings = vec![];
+    let mut pkgdep: BTreeSet<String> = depended
+        .iter()
+        .filter(|pkg| !ctx.is_implied(pkg))
+        .map(|pkg| pkg.to_string())
+        .collect();
+    for soname in missing {
+        let candidates = providers.find(soname)?;
+        if candidates.is_empty() {
+            findings.push(
+                Finding::new(Kind::NoProvider, format!("No package provides {}", soname))
+                    .soname(soname),
+            );
+            continue;
+        }
+        // prefer packages already depended on, instead of picking one arbitrarily
+        let preferred = candidates.iter().find(|pkg| {
+            pkgdep.contains(*pkg) || ctx.declared.contains(*pkg) || ctx.builtins.contains(*pkg)
+        });
+        let provider = match (preferred, candidates.as_slice()) {
+            (Some(provider), _) | (None, [provider]) => provider,
+            (None, _) => {
+                findings.push(
+                    Finding::new(
+                        Kind::AmbiguousProvider,
+                        format!(
+                            "{} is provided by {}, add one of them to PKGDEP",
+                            soname,
+                            candidates.join(", ")
+                        ),
+                    )
+                    .soname(soname),
+                );
+                continue;
+            }
+        };
+        if !ctx.builtins.contains(provider) && pkgdep.insert(provider.clone()) {
+            log::info!("Suggesting {} for {}", provider, soname);
+        }
+    }
+
+    let pkgdep = pkgdep
+        .into_iter()
+        .map(|pkg| versioned.get(pkg.as_str()).cloned().unwrap_or(pkg))
+        .collect();
+    Ok((pkgdep, findings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sodep::elf::{DynamicInfo, ElfArch, ElfClass};
+    use std::path::PathBuf;
+
+    const X86_64: ElfArch = ElfArch {
+        class: ElfClass::Elf64,
+        machine: 62,
+        osabi: 0,
+    };
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn library(path: &str) -> LibraryFile {
+        let soname = path.rsplit('/').next().unwrap().to_string();
+        let info = DynamicInfo {
+            arch: X86_64,
+            elf_type: 3,
+            interpreter: None,
+            soname: Some(soname),
+            needed: vec![],
+            rpath: vec![],
+            runpath: vec![],
+            flags: 0,
+            flags_1: 0,
+            has_symbols: false,
+            exported_symbols: vec![],
+            undefined_symbols: vec![],
+            version_needs: vec![],
+            version_defs: vec![],
+            errors: vec![],
+        };
+        (PathBuf::from(path), info)
+    }
+
+    fn binary(needed: &[&str]) -> LibraryDependency {
+        LibraryDependency {
+            name: "app".to_string(),
+            path: "/usr/bin/app".into(),
+            arch: X86_64,
+            needed: needed.iter().map(|s| s.to_string()).collect(),
+            rpath: vec![],
+            runpath: vec![],
+            has_symbols: false,
+            undefined_symbols: vec![],
+            version_needs: vec![],
+        }
+    }
+
+    /// Kind and package or soname of each finding
+    fn summary(findings: &[Finding]) -> Vec<(Kind, &str)> {
+        findings
+            .iter()
+            .map(|finding| {
+                let subject = finding.package.as_deref().or(finding.soname.as_deref());
+                (finding.kind, subject.unwrap_or_default())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dependency_unused() {
+        let builtins = set(&["glibc"]);
+        let declared = set(&["libfoo"]);
+        let ctx = Context {
+            package: "app",
+            builtins: &builtins,
+            declared: &declared,
+        };
+        let depends = ["libfoo", "libbar", "libbaz", "glibc"].map(str::to_string);
+        let depended = BTreeSet::from(["libbaz"]);
+
+        let findings = unused_dependencies(&ctx, &depends, &depended);
+        assert_eq!(
+            summary(&findings),
+            [
+                (Kind::UnusedDependency, "libfoo"),
+                (Kind::UnusedDependency, "libbar")
+            ]
+        );
+        assert!(findings[0].message.contains("declared in PKGDEP"));
+        assert!(!findings[1].message.contains("declared in PKGDEP"));
+    }
+
+    #[test]
+    fn soname_outside_search_path() {
+        let sources = vec![
+            ("libfoo", vec![library("/opt/foo/lib/libfoo.so.1")]),
+            ("libbar", vec![library("/usr/lib/libbar.so.1")]),
+        ];
+        let mut resolver = Resolver::new(vec![]);
+        let (libraries, duplicates) = index_libraries(&sources, &mut resolver);
+        assert!(duplicates.is_empty());
+
+        let deps = [binary(&["libfoo.so.1", "libbar.so.1", "libqux.so.1"])];
+        let resolved = resolve_needed(&deps, &resolver, &libraries);
+        assert_eq!(resolved.depended, BTreeSet::from(["libbar"]));
+        assert_eq!(resolved.missing, set(&["libfoo.so.1", "libqux.so.1"]));
+        assert_eq!(resolved.entries.len(), 3);
+        assert_eq!(
+            summary(&resolved.findings),
+            [
+                (Kind::UnsearchedSoname, "libfoo"),
+                (Kind::MissingSoname, "libqux.so.1")
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_soname() {
+        let sources = vec![
+            ("libfoo", vec![library("/usr/lib/libfoo.so.1")]),
+            ("libfoo-compat", vec![library("/usr/lib/libfoo.so.1")]),
+        ];
+        let mut resolver = Resolver::new(vec![]);
+        let (_, findings) = index_libraries(&sources, &mut resolver);
+        assert_eq!(
+            summary(&findings),
+            [(Kind::DuplicateProvider, "libfoo-compat")]
+        );
+    }
+
+    #[test]
+    fn pkgdep_undeclared() {
+        let builtins = set(&["glibc"]);
+        let declared = set(&["libfoo"]);
+        let ctx = Context {
+            package: "app",
+            builtins: &builtins,
+            declared: &declared,
+        };
+        let depended = BTreeSet::from(["app", "glibc", "libfoo", "libbar"]);
+        let missing = set(&["libbaz.so.1", "libm.so.7", "libnone.so.1", "libqux.so.1"]);
+        let providers = ProviderIndex::Contents(BTreeMap::from([
+            ("libbaz.so.1".to_string(), vec!["libbaz".to_string()]),
+            ("libm.so.7".to_string(), vec!["glibc".to_string()]),
+            (
+                "libqux.so.1".to_string(),
+                vec!["libqux".to_string(), "libqux-ng".to_string()],
+            ),
+        ]));
+
+        let findings = undeclared_dependencies(&ctx, &depended, &missing, &providers).unwrap();
+        assert_eq!(
+            summary(&findings),
+            [
+                (Kind::UndeclaredDependency, "libbar"),
+                (Kind::UndeclaredDependency, "libbaz"),
+                (Kind::UndeclaredDependency, "libqux.so.1")
+            ]
+        );
+    }
+
+    #[test]
+    fn suggest_from_providers() {
+        let builtins = set(&["glibc"]);
+        let declared = set(&["libqux-ng"]);
+        let ctx = Context {
+            package: "app",
+            builtins: &builtins,
+            declared: &declared,
+        };
+        let depended = BTreeSet::from(["app", "glibc", "libbar"]);
+        let missing = set(&["libbaz.so.1", "libnone.so.1", "libqux.so.1", "libzip.so.1"]);
+        let providers = ProviderIndex::Contents(BTreeMap::from([
+            ("libbaz.so.1".to_string(), vec!["libbaz".to_string()]),
+            (
+                "libqux.so.1".to_string(),
+                vec!["libqux".to_string(), "libqux-ng".to_string()],
+            ),
+            (
+                "libzip.so.1".to_string(),
+                vec!["libzip".to_string(), "libzip-ng".to_string()],
+            ),
+        ]));
+        let versioned = BTreeMap::from([("libbar", "libbar>=1.2".to_string())]);
+
+        let (pkgdep, findings) =
+            suggest_pkgdep(&ctx, &depended, &missing, &versioned, &providers).unwrap();
+        assert_eq!(pkgdep, ["libbar>=1.2", "libbaz", "libqux-ng"]);
+        assert_eq!(
+            summary(&findings),
+            [
+                (Kind::NoProvider, "libnone.so.1"),
+                (Kind::AmbiguousProvider, "libzip.so.1")
+            ]
+        );
+    }
+}
@@ -0,0 +1,967 @@
+//! Minimal ELF parser for what sodep needs from the dynamic section
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+const ET_DYN: u16 = 3;
+
+const ELFOSABI_NONE: u8 = 0;
+const ELFOSABI_GNU: u8 = 3;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const SHT_DYNAMIC: u32 = 6;
+const SHT_DYNSYM: u32 = 11;
+const SHT_GNU_VERDEF: u32 = 0x6ffffffd;
+const SHT_GNU_VERNEED: u32 = 0x6ffffffe;
+
+const VER_FLG_BASE: u16 = 0x1;
+
+const SHN_UNDEF: u16 = 0;
+
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+const STB_GNU_UNIQUE: u8 = 10;
+
+const STV_DEFAULT: u8 = 0;
+const STV_PROTECTED: u8 = 3;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_SONAME: u64 = 14;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+const DT_FLAGS: u64 = 30;
+const DT_FLAGS_1: u64 = 0x6ffffffb;
+const DT_GNU_HASH: u64 = 0x6ffffef5;
+
+const DF_1_PIE: u64 = 0x08000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ElfClass {
+    Elf32,
+    Elf64,
+}
+
+/// ELF class, machine and OS ABI, which must agree for objects to be loaded together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ElfArch {
+    pub class: ElfClass,
+    /// e_machine, e.g. 62 for x86_64
+    pub machine: u16,
+    /// EI_OSABI, e.g. 3 for GNU
+    pub osabi: u8,
+}
+
+impl ElfArch {
+    /// Whether an object of this architecture can load `other`
+    pub fn is_compatible(&self, other: &ElfArch) -> bool {
+        // GNU extensions only mark objects as ELFOSABI_GNU, they still load together
+        let generic = |osabi| matches!(osabi, ELFOSABI_NONE | ELFOSABI_GNU);
+        self.class == other.class
+            && self.machine == other.machine
+            && (self.osabi == other.osabi || (generic(self.osabi) && generic(other.osabi)))
+    }
+}
+
+impl Display for ElfArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match (self.machine, self.class) {
+            (3, _) => "i386",
+            (62, ElfClass::Elf64) => "x86_64",
+            (62, ElfClass::Elf32) => "x32",
+            (40, _) => "arm",
+            (183, _) => "aarch64",
+            (8, ElfClass::Elf32) => "mips",
+            (8, ElfClass::Elf64) => "mips64",
+            (20, _) => "powerpc",
+            (21, _) => "ppc64",
+            (22, ElfClass::Elf64) => "s390x",
+            (243, ElfClass::Elf32) => "riscv32",
+            (243, ElfClass::Elf64) => "riscv64",
+            (258, _) => "loongarch64",
+            (machine, class) => return write!(f, "machine {machine} ({class:?})"),
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Symbol versions required from a NEEDED library, read from .gnu.version_r
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct VersionNeed {
+    pub file: String,
+    pub versions: Vec<String>,
+}
+
+/// Information read from the dynamic section of an ELF file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DynamicInfo {
+    pub arch: ElfArch,
+    /// e_type, e.g. 3 for shared objects and position independent executables
+    pub elf_type: u16,
+    /// Program interpreter from PT_INTERP
+    pub interpreter: Option<String>,
+    pub soname: Option<String>,
+    pub needed: Vec<String>,
+    pub rpath: Vec<String>,
+    pub runpath: Vec<String>,
+    /// DT_FLAGS
+    pub flags: u64,
+    /// DT_FLAGS_1
+    pub flags_1: u64,
+    /// Whether the dynamic symbol table was read, symbols are not kept in the
+    /// scan cache
+    #[serde(skip)]
+    pub has_symbols: bool,
+    /// Dynamic symbols defined and visible to other objects
+    #[serde(skip)]
+    pub exported_symbols: Vec<String>,
+    /// Dynamic symbols to be resolved from other objects
+    #[serde(skip)]
+    pub undefined_symbols: Vec<String>,
+    /// Symbol versions required from other objects
+    pub version_needs: Vec<VersionNeed>,
+    /// Symbol versions defined in .gnu.version_d, except the base one
+    pub version_defs: Vec<String>,
+    /// Errors reading symbols or symbol versions, which leave the respective
+    /// fields empty but do not affect the dynamic section
+    #[serde(skip)]
+    pub errors: Vec<String>,
+}
+
+impl DynamicInfo {
+    /// Whether this is a shared object with a SONAME, or a DSO that can be
+    /// loaded but not executed
+    pub fn is_library(&self) -> bool {
+        self.elf_type == ET_DYN
+            && (self.soname.is_some()
+                || (self.flags_1 & DF_1_PIE == 0 && self.interpreter.is_none()))
+    }
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(ELF_MAGIC)
+}
+
+/// `base + delta`, failing instead of overflowing on corrupted offsets
+fn add(base: u64, delta: u64) -> anyhow::Result<u64> {
+    base.checked_add(delta)
+        .ok_or_else(|| anyhow!("ELF offset {:#x} + {:#x} overflows", base, delta))
+}
+
+/// Offset of the `index`-th entry of a table at `base`
+fn entry(base: u64, index: u64, entsize: u64) -> anyhow::Result<u64> {
+    index
+        .checked_mul(entsize)
+        .and_then(|off| base.checked_add(off))
+        .ok_or_else(|| anyhow!("ELF table entry {} at {:#x} overflows", index, base))
+}
+
+#[derive(Debug)]
+struct Section {
+    sh_type: u32,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+#[derive(Debug)]
+struct Segment {
+    p_type: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+/// Entries of the dynamic section, along with the file offset of its string table
+struct Dynamic {
+    entries: Vec<(u64, u64)>,
+    strtab: u64,
+}
+
+struct Elf<'a> {
+    data: &'a [u8],
+    class: ElfClass,
+    big_endian: bool,
+    osabi: u8,
+    elf_type: u16,
+    machine: u16,
+    sections: Vec<Section>,
+    segments: Vec<Segment>,
+}
+
+impl<'a> Elf<'a> {
+    fn parse(data: &'a [u8]) -> anyhow::Result<Self> {
+        if data.len() < 16 {
+            anyhow::bail!("ELF header is truncated")
+        }
+        let class = match data[4] {
+            1 => ElfClass::Elf32,
+            2 => ElfClass::Elf64,
+            other => anyhow::bail!("Unknown ELF class {}", other),
+        };
+        let big_endian = match data[5] {
+            1 => false,
+            2 => true,
+            other => anyhow::bail!("Unknown ELF data encoding {}", other),
+        };
+
+        let mut elf = Self {
+            data,
+            class,
+            big_endian,
+            osabi: data[7],
+            elf_type: 0,
+            machine: 0,
+            sections: vec![],
+            segments: vec![],
+        };
+        elf.elf_type = elf.u16(16)?;
+        elf.machine = elf.u16(18)?;
+
+        let (phoff, shoff, rest) = match class {
+            ElfClass::Elf32 => (elf.u32(28)? as u64, elf.u32(32)? as u64, 42),
+            ElfClass::Elf64 => (elf.u64(32)?, elf.u64(40)?, 54),
+        };
+        let phentsize = elf.u16(rest)? as u64;
+        let phnum = elf.u16(rest + 2)? as u64;
+        let shentsize = elf.u16(rest + 4)? as u64;
+        let shnum = elf.u16(rest + 6)? as u64;
+
+        for i in 0..phnum {
+            let off = entry(phoff, i, phentsize)?;
+            elf.segments.push(match class {
+                ElfClass::Elf32 => Segment {
+                    p_type: elf.u32(off)?,
+                    offset: elf.u32(add(off, 4)?)? as u64,
+                    vaddr: elf.u32(add(off, 8)?)? as u64,
+                    filesz: elf.u32(add(off, 16)?)? as u64,
+                },
+                ElfClass::Elf64 => Segment {
+                    p_type: elf.u32(off)?,
+                    offset: elf.u64(add(off, 8)?)?,
+                    vaddr: elf.u64(add(off, 16)?)?,
+                    filesz: elf.u64(add(off, 32)?)?,
+                },
+            });
+        }
+
+        for i in 0..shnum {
+            let off = entry(shoff, i, shentsize)?;
+            elf.sections.push(match class {
+                ElfClass::Elf32 => Section {
+                    sh_type: elf.u32(add(off, 4)?)?,
+                    offset: elf.u32(add(off, 16)?)? as u64,
+                    size: elf.u32(add(off, 20)?)? as u64,
+                    link: elf.u32(add(off, 24)?)?,
+                },
+                ElfClass::Elf64 => Section {
+                    sh_type: elf.u32(add(off, 4)?)?,
+                    offset: elf.u64(add(off, 24)?)?,
+                    size: elf.u64(add(off, 32)?)?,
+                    link: elf.u32(add(off, 40)?)?,
+                },
+            });
+        }
+
+        Ok(elf)
+    }
+
+    fn bytes(&self, off: u64, len: u64) -> anyhow::Result<&'a [u8]> {
+        let end = off.checked_add(len);
+        match end {
+            Some(end) if end <= self.data.len() as u64 => {
+                Ok(&self.data[off as usize..end as usize])
+            }
+            _ => anyhow::bail!("ELF offset {:#x} is out of bounds", off),
+        }
+    }
+
+    fn u16(&self, off: u64) -> anyhow::Result<u16> {
+        let bytes = self.bytes(off, 2)?.try_into()?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, off: u64) -> anyhow::Result<u32> {
+        let bytes = self.bytes(off, 4)?.try_into()?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, off: u64) -> anyhow::Result<u64> {
+        let bytes = self.bytes(off, 8)?.try_into()?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    /// Read an address-sized word
+    fn word(&self, off: u64) -> anyhow::Result<u64> {
+        match self.class {
+            ElfClass::Elf32 => Ok(self.u32(off)? as u64),
+            ElfClass::Elf64 => self.u64(off),
+        }
+    }
+
+    fn word_size(&self) -> u64 {
+        match self.class {
+            ElfClass::Elf32 => 4,
+            ElfClass::Elf64 => 8,
+        }
+    }
+
+    /// Map a virtual address to file offset with PT_LOAD segments
+    fn vaddr_to_offset(&self, vaddr: u64) -> Option<u64> {
+        self.segments
+            .iter()
+            .find(|seg| {
+                seg.p_type == PT_LOAD && vaddr >= seg.vaddr && vaddr - seg.vaddr < seg.filesz
+            })
+            .and_then(|seg| (vaddr - seg.vaddr).checked_add(seg.offset))
+    }
+
+    /// Read a NUL-terminated string
+    fn str(&self, off: u64) -> anyhow::Result<String> {
+        let rest = (self.data.len() as u64).saturating_sub(off);
+        let data = self.bytes(off, rest)?;
+        match data.iter().position(|b| *b == 0) {
+            Some(len) => Ok(String::from_utf8_lossy(&data[..len]).to_string()),
+            None => anyhow::bail!("ELF string at {:#x} is not terminated", off),
+        }
+    }
+
+    fn dynamic(&self) -> anyhow::Result<Option<Dynamic>> {
+        let (offset, size, strtab) =
+            match self.sections.iter().find(|sec| sec.sh_type == SHT_DYNAMIC) {
+                Some(sec) => (
+                    sec.offset,
+                    sec.size,
+                    self.sections.get(sec.link as usize).map(|link| link.offset),
+                ),
+                None => match self.segments.iter().find(|seg| seg.p_type == PT_DYNAMIC) {
+                    Some(seg) => (seg.offset, seg.filesz, None),
+                    None => return Ok(None),
+                },
+            };
+
+        let mut entries = vec![];
+        let entsize = self.word_size() * 2;
+        for i in 0..size / entsize {
+            let off = entry(offset, i, entsize)?;
+            let tag = self.word(off)?;
+            if tag == DT_NULL {
+                break;
+            }
+            entries.push((tag, self.word(add(off, self.word_size())?)?));
+        }
+
+        // fallback to DT_STRTAB for files without section headers
+        let strtab = match strtab {
+            Some(strtab) => strtab,
+            None => {
+                let Some(vaddr) = entries
+                    .iter()
+                    .find(|(tag, _)| *tag == DT_STRTAB)
+                    .map(|(_, val)| *val)
+                else {
+                    anyhow::bail!("Dynamic section has no string table")
+                };
+                match self.vaddr_to_offset(vaddr) {
+                    Some(off) => off,
+                    None => anyhow::bail!("DT_STRTAB {:#x} is not mapped", vaddr),
+                }
+            }
+        };
+        Ok(Some(Dynamic { entries, strtab }))
+    }
+
+    fn sym_size(&self) -> u64 {
+        match self.class {
+            ElfClass::Elf32 => 16,
+            ElfClass::Elf64 => 24,
+        }
+    }
+
+    /// Number of symbols covered by a .gnu.hash table, which is the highest
+    /// symbol index in its chains plus one
+    fn gnu_hash_symbols(&self, off: u64) -> anyhow::Result<u64> {
+        let nbuckets = self.u32(off)? as u64;
+        let symoffset = self.u32(add(off, 4)?)? as u64;
+        let bloom_size = self.u32(add(off, 8)?)? as u64;
+        let buckets = entry(add(off, 16)?, bloom_size, self.word_size())?;
+        let chains = entry(buckets, nbuckets, 4)?;
+
+        let mut last = 0;
+        for i in 0..nbuckets {
+            last = last.max(self.u32(entry(buckets, i, 4)?)? as u64);
+        }
+        if last < symoffset {
+            return Ok(symoffset);
+        }
+        // the last chain ends with an entry whose lowest bit is set
+        while self.u32(entry(chains, last - symoffset, 4)?)? & 1 == 0 {
+            last += 1;
+        }
+        Ok(last + 1)
+    }
+
+    /// File offsets of the dynamic symbol table and its string table, with the
+    /// number of symbols, found with DT_SYMTAB and hash tables if .dynsym is
+    /// stripped
+    fn dynamic_symbol_table(&self, dynamic: &Dynamic) -> anyhow::Result<Option<(u64, u64, u64)>> {
+        if let Some(dynsym) = self.sections.iter().find(|sec| sec.sh_type == SHT_DYNSYM) {
+            let Some(strtab) = self.sections.get(dynsym.link as usize) else {
+                anyhow::bail!(".dynsym has no string table")
+            };
+            return Ok(Some((
+                dynsym.offset,
+                dynsym.size / self.sym_size(),
+                strtab.offset,
+            )));
+        }
+
+        let offset_of = |tag: u64| {
+            dynamic
+                .entries
+                .iter()
+                .find(|(cur, _)| *cur == tag)
+                .and_then(|(_, vaddr)| self.vaddr_to_offset(*vaddr))
+        };
+        let Some(symtab) = offset_of(DT_SYMTAB) else {
+            return Ok(None);
+        };
+        let count = if let Some(hash) = offset_of(DT_HASH) {
+            // nchain is the number of symbols
+            self.u32(add(hash, 4)?)? as u64
+        } else if let Some(gnu_hash) = offset_of(DT_GNU_HASH) {
+            self.gnu_hash_symbols(gnu_hash)?
+        } else {
+            return Ok(None);
+        };
+        Ok(Some((symtab, count, dynamic.strtab)))
+    }
+
+    /// Names of exported and undefined dynamic symbols, `None` if the symbol
+    /// table is not found
+    fn dynamic_symbols(
+        &self,
+        dynamic: &Dynamic,
+    ) -> anyhow::Result<Option<(Vec<String>, Vec<String>)>> {
+        let Some((symtab, count, strtab)) = self.dynamic_symbol_table(dynamic)? else {
+            return Ok(None);
+        };
+
+        let mut exported = vec![];
+        let mut undefined = vec![];
+        let entsize = self.sym_size();
+        // skip the null symbol
+        for i in 1..count {
+            let off = entry(symtab, i, entsize)?;
+            // st_info, st_other and st_shndx are adjacent in both classes
+            let info_off = match self.class {
+                ElfClass::Elf32 => add(off, 12)?,
+                ElfClass::Elf64 => add(off, 4)?,
+            };
+            let name = self.u32(off)?;
+            let [info, other] = self.bytes(info_off, 2)?.try_into()?;
+            let shndx = self.u16(add(info_off, 2)?)?;
+            if name == 0 {
+                continue;
+            }
+
+            if shndx == SHN_UNDEF {
+                undefined.push(self.str(add(strtab, name as u64)?)?);
+            } else if matches!(info >> 4, STB_GLOBAL | STB_WEAK | STB_GNU_UNIQUE)
+                && matches!(other & 0x3, STV_DEFAULT | STV_PROTECTED)
+            {
+                exported.push(self.str(add(strtab, name as u64)?)?);
+            }
+        }
+        Ok(Some((exported, undefined)))
+    }
+
+    /// Entries of .gnu.version_r
+    fn version_needs(&self) -> anyhow::Result<Vec<VersionNeed>> {
+        let mut res = vec![];
+        let Some(verneed) = self
+            .sections
+            .iter()
+            .find(|sec| sec.sh_type == SHT_GNU_VERNEED)
+        else {
+            return Ok(res);
+        };
+        let Some(strtab) = self.sections.get(verneed.link as usize) else {
+            anyhow::bail!(".gnu.version_r has no string table")
+        };
+
+        // Elf_Verneed and Elf_Vernaux have the same layout in ELF32 and ELF64
+        let mut off = verneed.offset;
+        loop {
+            let cnt = self.u16(add(off, 2)?)?;
+            let file = self.str(add(strtab.offset, self.u32(add(off, 4)?)? as u64)?)?;
+            let mut versions = vec![];
+            let mut aux = add(off, self.u32(add(off, 8)?)? as u64)?;
+            for _ in 0..cnt {
+                let name = self.u32(add(aux, 8)?)?;
+                versions.push(self.str(add(strtab.offset, name as u64)?)?);
+                match self.u32(add(aux, 12)?)? {
+                    0 => break,
+                    next => aux = add(aux, next as u64)?,
+                }
+            }
+            res.push(VersionNeed { file, versions });
+
+            match self.u32(add(off, 12)?)? {
+                0 => break,
+                next => off = add(off, next as u64)?,
+            }
+        }
+        Ok(res)
+    }
+
+    /// Names of versions in .gnu.version_d
+    fn version_defs(&self) -> anyhow::Result<Vec<String>> {
+        let mut res = vec![];
+        let Some(verdef) = self
+            .sections
+            .iter()
+            .find(|sec| sec.sh_type == SHT_GNU_VERDEF)
+        else {
+            return Ok(res);
+        };
+        let Some(strtab) = self.sections.get(verdef.link as usize) else {
+            anyhow::bail!(".gnu.version_d has no string table")
+        };
+
+        // the first Elf_Verdaux names the version, the rest are its parents
+        let mut off = verdef.offset;
+        loop {
+            let flags = self.u16(add(off, 2)?)?;
+            let cnt = self.u16(add(off, 6)?)?;
+            if flags & VER_FLG_BASE == 0 && cnt > 0 {
+                let aux = add(off, self.u32(add(off, 12)?)? as u64)?;
+                res.push(self.str(add(strtab.offset, self.u32(aux)? as u64)?)?);
+            }
+
+            match self.u32(add(off, 16)?)? {
+                0 => break,
+                next => off = add(off, next as u64)?,
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Parse the dynamic section of an ELF file, returns `None` if it is not ELF
+pub fn parse(data: &[u8]) -> anyhow::Result<Option<DynamicInfo>> {
+    if !is_elf(data) {
+        return Ok(None);
+    }
+
+    let elf = Elf::parse(data)?;
+    let interpreter = match elf.segments.iter().find(|seg| seg.p_type == PT_INTERP) {
+        Some(seg) => Some(elf.str(seg.offset)?),
+        None => None,
+    };
+    let mut res = DynamicInfo {
+        arch: ElfArch {
+            class: elf.class,
+            machine: elf.machine,
+            osabi: elf.osabi,
+        },
+        elf_type: elf.elf_type,
+        interpreter,
+        soname: None,
+        needed: vec![],
+        rpath: vec![],
+        runpath: vec![],
+        flags: 0,
+        flags_1: 0,
+        has_symbols: false,
+        exported_symbols: vec![],
+        undefined_symbols: vec![],
+        version_needs: vec![],
+        version_defs: vec![],
+        errors: vec![],
+    };
+
+    // statically linked
+    let Some(dynamic) = elf.dynamic()? else {
+        return Ok(Some(res));
+    };
+    let strtab = dynamic.strtab;
+
+    let split_paths = |value: String| -> Vec<String> {
+        value
+            .split(':')
+            .filter(|path| !path.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    for &(tag, val) in &dynamic.entries {
+        match tag {
+            DT_NEEDED => res.needed.push(elf.str(add(strtab, val)?)?),
+            DT_SONAME => res.soname = Some(elf.str(add(strtab, val)?)?),
+            DT_RPATH => res.rpath.extend(split_paths(elf.str(add(strtab, val)?)?)),
+            DT_RUNPATH => res.runpath.extend(split_paths(elf.str(add(strtab, val)?)?)),
+            DT_FLAGS => res.flags = val,
+            DT_FLAGS_1 => res.flags_1 = val,
+            _ => {}
+        }
+    }
+    match elf.dynamic_symbols(&dynamic) {
+        Ok(Some((exported, undefined))) => {
+            res.has_symbols = true;
+            res.exported_symbols = exported;
+            res.undefined_symbols = undefined;
+        }
+        Ok(None) => {}
+        Err(err) => res.errors.push(format!("dynamic symbols: {}", err)),
+    }
+    match elf.version_needs() {
+        Ok(needs) => res.version_needs = needs,
+        Err(err) => res.errors.push(format!(".gnu.version_r: {}", err)),
+    }
+    match elf.version_defs() {
+        Ok(defs) => res.version_defs = defs,
+        Err(err) => res.errors.push(format!(".gnu.version_d: {}", err)),
+    }
+    Ok(Some(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRTAB: &[u8] = b"\0libfoo.so.1\0libbar.so.2\0$ORIGIN/../lib:/opt\0foo\0bar\0";
+    const SHT_STRTAB: u32 = 3;
+    const DT_NEEDED_FOO: u64 = 1;
+    const DT_SONAME_BAR: u64 = 13;
+    const DT_RUNPATH_VAL: u64 = 25;
+    const SYM_FOO: u32 = 45;
+    const SYM_BAR: u32 = 49;
+
+    /// Writes fields in the byte order and word size of an ELF file
+    struct Writer {
+        elf64: bool,
+        big_endian: bool,
+        buf: Vec<u8>,
+    }
+
+    impl Writer {
+        fn u8(&mut self, val: u8) {
+            self.buf.push(val);
+        }
+
+        fn u16(&mut self, val: u16) {
+            let bytes = if self.big_endian {
+                val.to_be_bytes()
+            } else {
+                val.to_le_bytes()
+            };
+            self.buf.extend(bytes);
+        }
+
+        fn u32(&mut self, val: u32) {
+            let bytes = if self.big_endian {
+                val.to_be_bytes()
+            } else {
+                val.to_le_bytes()
+            };
+            self.buf.extend(bytes);
+        }
+
+        fn u64(&mut self, val: u64) {
+            let bytes = if self.big_endian {
+                val.to_be_bytes()
+            } else {
+                val.to_le_bytes()
+            };
+            self.buf.extend(bytes);
+        }
+
+        fn word(&mut self, val: u64) {
+            if self.elf64 {
+                self.u64(val);
+            } else {
+                self.u32(val as u32);
+            }
+        }
+
+        fn phdr(&mut self, p_type: u32, offset: u64, size: u64) {
+            self.u32(p_type);
+            if self.elf64 {
+                // p_flags
+                self.u32(0);
+            }
+            // p_offset, p_vaddr, p_paddr, p_filesz, p_memsz, with vaddr == offset
+            for val in [offset, offset, offset, size, size] {
+                self.word(val);
+            }
+            if !self.elf64 {
+                self.u32(0);
+            }
+            // p_align
+            self.word(0);
+        }
+
+        fn sym(&mut self, name: u32, shndx: u16) {
+            // STB_GLOBAL, STT_FUNC
+            let info = (STB_GLOBAL << 4) | 2;
+            self.u32(name);
+            if self.elf64 {
+                self.u8(info);
+                self.u8(STV_DEFAULT);
+                self.u16(shndx);
+                self.u64(0);
+                self.u64(0);
+            } else {
+                self.u32(0);
+                self.u32(0);
+                self.u8(info);
+                self.u8(STV_DEFAULT);
+                self.u16(shndx);
+            }
+        }
+
+        fn shdr(&mut self, sh_type: u32, offset: u64, size: u64, link: u32) {
+            // sh_name, sh_type, sh_flags, sh_addr
+            self.u32(0);
+            self.u32(sh_type);
+            self.word(0);
+            self.word(offset);
+            self.word(offset);
+            self.word(size);
+            self.u32(link);
+            // sh_info, sh_addralign, sh_entsize
+            self.u32(0);
+            self.word(0);
+            self.word(0);
+        }
+    }
+
+    /// How the symbol table is found without section headers
+    #[derive(Clone, Copy)]
+    enum Hash {
+        None,
+        Sysv,
+        Gnu,
+    }
+
+    /// A shared library named libbar.so.2 needing libfoo.so.1, exporting `foo`
+    /// and importing `bar`, loaded with a PT_LOAD covering the whole file
+    fn build(elf64: bool, big_endian: bool, with_sections: bool, hash: Hash) -> Vec<u8> {
+        let (ehsize, phentsize, symsize, shentsize, word) = if elf64 {
+            (64, 56, 24, 64, 8)
+        } else {
+            (52, 32, 16, 40, 4)
+        };
+        let phoff = ehsize;
+        // dynamic section first, so that tests can find DT_NEEDED easily
+        let dynamic = phoff + 2 * phentsize;
+        let dynamic_size = 7 * 2 * word;
+        let strtab = dynamic + dynamic_size;
+        let dynsym = strtab + STRTAB.len() as u64;
+        let hash_table = dynsym + 3 * symsize;
+        let hash_size = match hash {
+            Hash::None => 0,
+            // nbucket, nchain, one bucket, three chains
+            Hash::Sysv => 4 * 5,
+            // header, one bloom word, one bucket, two chains
+            Hash::Gnu => 16 + word + 4 + 2 * 4,
+        };
+        let shoff = hash_table + hash_size;
+        let shnum = if with_sections { 4 } else { 0 };
+        let size = shoff + shnum * shentsize;
+
+        let mut w = Writer {
+            elf64,
+            big_endian,
+            buf: vec![],
+        };
+        w.buf.extend(ELF_MAGIC);
+        w.u8(if elf64 { 2 } else { 1 });
+        w.u8(if big_endian { 2 } else { 1 });
+        w.u8(1);
+        w.u8(ELFOSABI_GNU);
+        w.buf.resize(16, 0);
+        w.u16(ET_DYN);
+        w.u16(if elf64 { 62 } else { 3 });
+        w.u32(1);
+        // e_entry, e_phoff, e_shoff
+        w.word(0);
+        w.word(phoff);
+        w.word(if with_sections { shoff } else { 0 });
+        w.u32(0);
+        w.u16(ehsize as u16);
+        w.u16(phentsize as u16);
+        w.u16(2);
+        w.u16(shentsize as u16);
+        w.u16(shnum as u16);
+        w.u16(0);
+        assert_eq!(w.buf.len() as u64, phoff);
+
+        w.phdr(PT_LOAD, 0, size);
+        w.phdr(PT_DYNAMIC, dynamic, dynamic_size);
+
+        let hash_entry = match hash {
+            Hash::None => (DT_NULL, 0),
+            Hash::Sysv => (DT_HASH, hash_table),
+            Hash::Gnu => (DT_GNU_HASH, hash_table),
+        };
+        for (tag, val) in [
+            (DT_NEEDED, DT_NEEDED_FOO),
+            (DT_SONAME, DT_SONAME_BAR),
+            (DT_RUNPATH, DT_RUNPATH_VAL),
+            (DT_STRTAB, strtab),
+            (DT_SYMTAB, dynsym),
+            hash_entry,
+            (DT_NULL, 0),
+        ] {
+            w.word(tag);
+            w.word(val);
+        }
+
+        w.buf.extend(STRTAB);
+        w.sym(0, 0);
+        w.sym(SYM_FOO, 1);
+        w.sym(SYM_BAR, SHN_UNDEF);
+
+        match hash {
+            Hash::None => {}
+            Hash::Sysv => {
+                for val in [1, 3, 0, 0, 0] {
+                    w.u32(val);
+                }
+            }
+            Hash::Gnu => {
+                // nbuckets, symoffset, bloom_size, bloom_shift
+                for val in [1, 1, 1, 0] {
+                    w.u32(val);
+                }
+                w.word(u64::MAX);
+                w.u32(1);
+                // hashes of foo and bar, the last one ending the chain
+                w.u32(0x1000);
+                w.u32(0x2001);
+            }
+        }
+
+        if with_sections {
+            w.shdr(0, 0, 0, 0);
+            w.shdr(SHT_STRTAB, strtab, STRTAB.len() as u64, 0);
+            w.shdr(SHT_DYNSYM, dynsym, 3 * symsize, 1);
+            w.shdr(SHT_DYNAMIC, dynamic, dynamic_size, 1);
+        }
+        assert_eq!(w.buf.len() as u64, size);
+        w.buf
+    }
+
+    fn check(elf64: bool, big_endian: bool) {
+        for (with_sections, hash) in [
+            (true, Hash::None),
+            (false, Hash::Sysv),
+            (false, Hash::Gnu),
+            (false, Hash::None),
+        ] {
+            let info = parse(&build(elf64, big_endian, with_sections, hash))
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                info.arch,
+                ElfArch {
+                    class: if elf64 {
+                        ElfClass::Elf64
+                    } else {
+                        ElfClass::Elf32
+                    },
+                    machine: if elf64 { 62 } else { 3 },
+                    osabi: ELFOSABI_GNU,
+                }
+            );
+            assert_eq!(info.soname.as_deref(), Some("libbar.so.2"));
+            assert_eq!(info.needed, ["libfoo.so.1"]);
+            assert_eq!(info.runpath, ["$ORIGIN/../lib", "/opt"]);
+            assert!(info.is_library());
+            if with_sections || !matches!(hash, Hash::None) {
+                assert!(info.has_symbols);
+                assert_eq!(info.exported_symbols, ["foo"]);
+                assert_eq!(info.undefined_symbols, ["bar"]);
+            } else {
+                assert!(!info.has_symbols);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_elf32_little_endian() {
+        check(false, false);
+    }
+
+    #[test]
+    fn parse_elf32_big_endian() {
+        check(false, true);
+    }
+
+    #[test]
+    fn parse_elf64_little_endian() {
+        check(true, false);
+    }
+
+    #[test]
+    fn parse_elf64_big_endian() {
+        check(true, true);
+    }
+
+    #[test]
+    fn parse_not_elf() {
+        assert!(parse(b"#!/bin/sh\n").unwrap().is_none());
+    }
+
+    #[test]
+    fn reject_overflowing_offsets() {
+        let mut data = build(true, false, true, Hash::None);
+        // e_shoff near u64::MAX
+        data[40..48].copy_from_slice(&(u64::MAX - 8).to_le_bytes());
+        assert!(parse(&data).is_err());
+
+        let mut data = build(true, false, false, Hash::None);
+        // value of DT_NEEDED, the first entry of the dynamic section
+        let needed = 64 + 2 * 56 + 8;
+        data[needed..needed + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn keep_dynamic_section_with_broken_symbols() {
+        let mut data = build(true, false, true, Hash::None);
+        // sh_link of .dynsym, the third section header
+        let shoff = u64::from_le_bytes(data[40..48].try_into().unwrap()) as usize;
+        let link = shoff + 2 * 64 + 40;
+        data[link..link + 4].copy_from_slice(&9u32.to_le_bytes());
+
+        let info = parse(&data).unwrap().unwrap();
+        assert_eq!(info.soname.as_deref(), Some("libbar.so.2"));
+        assert_eq!(info.needed, ["libfoo.so.1"]);
+        assert!(!info.has_symbols);
+        assert!(info.exported_symbols.is_empty());
+        assert_eq!(info.errors.len(), 1);
+    }
+}
@@ -0,0 +1,249 @@
+//! Typed findings of sodep, reported as logs, JSON or SARIF
+
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::json;
+use strum::{EnumIter, EnumMessage, IntoEnumIterator, IntoStaticStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Level name in SARIF
+    fn sarif_level(self) -> &'static str {
+        match self {
+            Self::Note => "note",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Kinds of findings, described by their messages. `OverLinking` is about one
+/// NEEDED entry of a binary, `UnusedSymbols` about a dependency package none of
+/// whose symbols are used by any binary
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    EnumIter,
+    EnumMessage,
+    IntoStaticStr,
+)]
+#[serde(into = "&'static str")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Kind {
+    #[strum(message = "No dependency ships a NEEDED soname")]
+    MissingSoname,
+    #[strum(message = "A NEEDED soname is shipped, but outside the library search path")]
+    UnsearchedSoname,
+    #[strum(message = "A NEEDED soname is only shipped for other architectures")]
+    ArchMismatch,
+    #[strum(message = "A library in the closure misses a NEEDED soname")]
+    TransitiveMissingSoname,
+    #[strum(message = "A required symbol version is not defined by the library")]
+    MissingSymbolVersion,
+    #[strum(message = "A dependency provides nothing the package links to")]
+    UnusedDependency,
+    #[strum(message = "A soname is shipped by more than one package")]
+    DuplicateProvider,
+    #[strum(message = "A binary has a NEEDED library none of whose symbols it uses")]
+    OverLinking,
+    #[strum(message = "A dependency only used through other dependencies")]
+    TransitiveDependency,
+    #[strum(message = "No binary uses any symbol of a linked dependency")]
+    UnusedSymbols,
+    #[strum(message = "No package provides a missing soname")]
+    NoProvider,
+    #[strum(message = "A package providing a NEEDED soname is not declared in PKGDEP")]
+    UndeclaredDependency,
+    #[strum(message = "Several packages provide a missing soname, none of them a dependency")]
+    AmbiguousProvider,
+    #[strum(message = "A package declared in PKGDEP is neither installed nor in the index")]
+    UnavailableDependency,
+}
+
+impl Kind {
+    pub fn severity(self) -> Severity {
+        match self {
+            Kind::MissingSoname
+            | Kind::UnsearchedSoname
+            | Kind::ArchMismatch
+            | Kind::TransitiveMissingSoname
+            | Kind::MissingSymbolVersion
+            | Kind::NoProvider
+            | Kind::UndeclaredDependency => Severity::Error,
+            Kind::UnusedDependency
+            | Kind::DuplicateProvider
+            | Kind::OverLinking
+            | Kind::TransitiveDependency
+            | Kind::UnusedSymbols
+            | Kind::AmbiguousProvider
+            | Kind::UnavailableDependency => Severity::Warning,
+        }
+    }
+
+    /// Rule id, same as the serialized name
+    pub fn id(self) -> &'static str {
+        self.into()
+    }
+
+    fn description(self) -> &'static str {
+        self.get_message().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub kind: Kind,
+    pub severity: Severity,
+    pub message: String,
+    /// The binary this is about
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub soname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+}
+
+impl Finding {
+    pub fn new(kind: Kind, message: String) -> Self {
+        Self {
+            kind,
+            severity: kind.severity(),
+            message,
+            file: None,
+            soname: None,
+            package: None,
+        }
+    }
+
+    pub fn file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    pub fn soname(mut self, soname: &str) -> Self {
+        self.soname = Some(soname.to_string());
+        self
+    }
+
+    pub fn package(mut self, package: &str) -> Self {
+        self.package = Some(package.to_string());
+        self
+    }
+}
+
+/// Findings collected during a run, logged as they are pushed
+#[derive(Debug, Default)]
+pub struct Findings(Vec<Finding>);
+
+impl Extend<Finding> for Findings {
+    fn extend<T: IntoIterator<Item = Finding>>(&mut self, iter: T) {
+        for finding in iter {
+            self.push(finding);
+        }
+    }
+}
+
+impl Findings {
+    pub fn push(&mut self, finding: Finding) {
+        match finding.severity {
+            Severity::Error => error!("{}", finding.message),
+            Severity::Warning => warn!("{}", finding.message),
+            Severity::Note => info!("{}", finding.message),
+        }
+        self.0.push(finding);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Finding> {
+        self.0.iter()
+    }
+
+    /// The highest severity of all findings
+    pub fn severity(&self) -> Option<Severity> {
+        self.0.iter().map(|finding| finding.severity).max()
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(&self.0)?)
+    }
+
+    /// Format as a SARIF 2.1.0 log
+    pub fn to_sarif(&self, tool: &str, version: &str) -> anyhow::Result<String> {
+        let rules: Vec<_> = Kind::iter()
+            .map(|kind| {
+                json!({
+                    "id": kind.id(),
+                    "shortDescription": { "text": kind.description() },
+                    "defaultConfiguration": { "level": kind.severity().sarif_level() },
+                })
+            })
+            .collect();
+        let results: Vec<_> = self
+            .0
+            .iter()
+            .map(|finding| {
+                let mut result = json!({
+                    "ruleId": finding.kind.id(),
+                    "level": finding.severity.sarif_level(),
+                    "message": { "text": finding.message },
+                });
+                if let Some(file) = &finding.file {
+                    result["locations"] = json!([{
+                        "physicalLocation": { "artifactLocation": { "uri": file } },
+                    }]);
+                }
+                let mut properties = serde_json::Map::new();
+                if let Some(soname) = &finding.soname {
+                    properties.insert("soname".into(), soname.as_str().into());
+                }
+                if let Some(package) = &finding.package {
+                    properties.insert("package".into(), package.as_str().into());
+                }
+                if !properties.is_empty() {
+                    result["properties"] = properties.into();
+                }
+                result
+            })
+            .collect();
+
+        Ok(serde_json::to_string_pretty(&json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": tool,
+                        "version": version,
+                        "rules": rules,
+                    },
+                },
+                "results": results,
+            }],
+        }))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_kind_has_a_description() {
+        for kind in Kind::iter() {
+            assert!(!kind.description().is_empty(), "{}", kind.id());
+        }
+    }
+}
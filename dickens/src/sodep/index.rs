@@ -0,0 +1,203 @@
+//! Soname index of a whole repository
+
+use super::{elf::ElfArch, LibraryDependency, LibraryFile};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+/// Packages of one architecture, or of several compatible ones
+#[derive(Debug, Serialize)]
+pub struct ArchPackages {
+    /// Architecture of the first file added
+    pub arch: ElfArch,
+    pub packages: BTreeSet<String>,
+}
+
+/// Map soname => packages grouped by compatible architectures
+type ByArch = BTreeMap<String, Vec<ArchPackages>>;
+
+/// Add `package` to the group compatible with `arch`
+fn insert(groups: &mut Vec<ArchPackages>, arch: &ElfArch, package: &str) {
+    match groups
+        .iter_mut()
+        .find(|group| group.arch.is_compatible(arch))
+    {
+        Some(group) => {
+            group.packages.insert(package.to_string());
+        }
+        None => groups.push(ArchPackages {
+            arch: *arch,
+            packages: BTreeSet::from([package.to_string()]),
+        }),
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RepoIndex {
+    /// Packages shipping each soname, per architecture
+    pub providers: ByArch,
+    /// Map file name => sonames, for symlinks and real files named differently
+    pub aliases: BTreeMap<String, BTreeSet<String>>,
+    /// Packages with files needing each soname, per architecture
+    pub needed_by: ByArch,
+}
+
+impl RepoIndex {
+    /// Add libraries and dependencies of a package, as returned by
+    /// `Scanner::get_library_files_and_deps`
+    pub fn add(&mut self, package: &str, libraries: &[LibraryFile], deps: &[LibraryDependency]) {
+        for (path, info) in libraries {
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            let name = name.to_string_lossy().to_string();
+            let soname = info.soname.clone().unwrap_or_else(|| name.clone());
+            if name != soname {
+                self.aliases.entry(name).or_default().insert(soname.clone());
+            }
+            insert(
+                self.providers.entry(soname).or_default(),
+                &info.arch,
+                package,
+            );
+        }
+        for dep in deps {
+            for needed in &dep.needed {
+                insert(
+                    self.needed_by.entry(needed.clone()).or_default(),
+                    &dep.arch,
+                    package,
+                );
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Sonames shipped by more than one package for the same architecture
+    pub fn duplicates(&self) -> impl Iterator<Item = (&String, &ElfArch, &BTreeSet<String>)> {
+        self.providers.iter().flat_map(|(soname, groups)| {
+            groups
+                .iter()
+                .filter(|group| group.packages.len() > 1)
+                .map(move |group| (soname, &group.arch, &group.packages))
+        })
+    }
+
+    /// Architectures `needed` is shipped for, by soname or file name
+    pub fn provided_arches(&self, needed: &str) -> BTreeSet<&ElfArch> {
+        let mut res = BTreeSet::new();
+        let sonames = self.aliases.get(needed).into_iter().flatten();
+        for soname in std::iter::once(needed).chain(sonames.map(String::as_str)) {
+            if let Some(groups) = self.providers.get(soname) {
+                res.extend(groups.iter().map(|group| &group.arch));
+            }
+        }
+        res
+    }
+
+    /// NEEDED sonames no package ships for the architecture of the packages
+    /// needing them
+    pub fn unprovided(&self) -> impl Iterator<Item = (&String, &ElfArch, &BTreeSet<String>)> {
+        self.needed_by.iter().flat_map(move |(soname, groups)| {
+            let provided = self.provided_arches(soname);
+            groups
+                .iter()
+                .filter(move |group| !provided.iter().any(|arch| group.arch.is_compatible(arch)))
+                .map(move |group| (soname, &group.arch, &group.packages))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sodep::elf::{DynamicInfo, ElfClass};
+    use std::path::PathBuf;
+
+    fn arch(osabi: u8) -> ElfArch {
+        ElfArch {
+            class: ElfClass::Elf64,
+            machine: 62,
+            osabi,
+        }
+    }
+
+    fn library(soname: &str, arch: ElfArch) -> LibraryFile {
+        let info = DynamicInfo {
+            arch,
+            elf_type: 3,
+            interpreter: None,
+            soname: Some(soname.to_string()),
+            needed: vec![],
+            rpath: vec![],
+            runpath: vec![],
+            flags: 0,
+            flags_1: 0,
+            has_symbols: false,
+            exported_symbols: vec![],
+            undefined_symbols: vec![],
+            version_needs: vec![],
+            version_defs: vec![],
+            errors: vec![],
+        };
+        (PathBuf::from(format!("/usr/lib/{soname}")), info)
+    }
+
+    fn dependency(needed: &str, arch: ElfArch) -> LibraryDependency {
+        LibraryDependency {
+            name: "app".to_string(),
+            path: PathBuf::from("/usr/bin/app"),
+            arch,
+            needed: vec![needed.to_string()],
+            rpath: vec![],
+            runpath: vec![],
+            has_symbols: false,
+            undefined_symbols: vec![],
+            version_needs: vec![],
+        }
+    }
+
+    #[test]
+    fn groups_compatible_arches() {
+        let mut index = RepoIndex::default();
+        // ELFOSABI_NONE and ELFOSABI_GNU load together
+        index.add("foo", &[library("libfoo.so.1", arch(0))], &[]);
+        index.add("foo-gnu", &[library("libfoo.so.1", arch(3))], &[]);
+        index.add("app", &[], &[dependency("libfoo.so.1", arch(3))]);
+
+        let duplicates: Vec<_> = index.duplicates().collect();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(
+            duplicates[0].2,
+            &BTreeSet::from(["foo".to_string(), "foo-gnu".to_string()])
+        );
+        assert_eq!(index.unprovided().count(), 0);
+    }
+
+    #[test]
+    fn separates_incompatible_osabi() {
+        let mut index = RepoIndex::default();
+        // ELFOSABI_FREEBSD
+        index.add("foo", &[library("libfoo.so.1", arch(9))], &[]);
+        index.add("foo-gnu", &[library("libfoo.so.1", arch(3))], &[]);
+        index.add("bsd", &[library("libbsd.so.1", arch(9))], &[]);
+        index.add("app", &[], &[dependency("libbsd.so.1", arch(0))]);
+
+        assert_eq!(index.duplicates().count(), 0);
+        let unprovided: Vec<_> = index
+            .unprovided()
+            .map(|(soname, _, packages)| (soname.as_str(), packages.clone()))
+            .collect();
+        assert_eq!(
+            unprovided,
+            [("libbsd.so.1", BTreeSet::from(["app".to_string()]))]
+        );
+        assert_eq!(index.provided_arches("libbsd.so.1").len(), 1);
+    }
+}
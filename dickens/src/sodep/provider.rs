@@ -0,0 +1,181 @@
+use super::resolve::is_library_dir;
+use crate::deb::decompress;
+use log::{info, warn};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Where to look for packages providing a soname
+pub enum ProviderIndex {
+    /// Search installed packages with `dpkg -S`
+    Installed,
+    /// Map file name => packages, read from a Contents index
+    Contents(BTreeMap<String, Vec<String>>),
+}
+
+/// Whether a file at `path`, relative to the root or not, is in a directory the
+/// loader searches by default
+fn in_library_dir(path: &str) -> bool {
+    Path::new("/")
+        .join(path)
+        .parent()
+        .is_some_and(is_library_dir)
+}
+
+/// Contents index of `arch` in `dir`, optionally compressed
+fn contents_of(dir: &Path, arch: &str) -> anyhow::Result<PathBuf> {
+    for ext in ["", ".gz", ".xz", ".zst"] {
+        let path = dir.join(format!("Contents-{arch}{ext}"));
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+    anyhow::bail!("No Contents index of {} in {}", arch, dir.display())
+}
+
+impl ProviderIndex {
+    /// Read a Contents index of `arch`, optionally compressed. If `path` is a
+    /// directory, `Contents-{arch}` in it is read
+    pub fn from_contents(path: &Path, arch: &str) -> anyhow::Result<Self> {
+        let path = if path.is_dir() {
+            contents_of(path, arch)?
+        } else {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if let Some(index_arch) = name.strip_prefix("Contents-") {
+                let index_arch = index_arch.split('.').next().unwrap_or_default();
+                if index_arch != arch {
+                    warn!(
+                        "{} looks like the Contents index of {}, not {}",
+                        path.display(),
+                        index_arch,
+                        arch
+                    );
+                }
+            }
+            path.to_path_buf()
+        };
+
+        info!("Reading Contents index {}", path.display());
+        let name = path.to_string_lossy();
+        let file = File::open(&path)?;
+        let reader: Box<dyn Read> =
+            if name.ends_with(".gz") || name.ends_with(".xz") || name.ends_with(".zst") {
+                decompress(&name, file)?
+            } else {
+                Box::new(file)
+            };
+        Ok(Self::Contents(read_contents(BufReader::new(reader))?))
+    }
+
+    /// Find packages shipping a file named `soname` in a library directory
+    pub fn find(&self, soname: &str) -> anyhow::Result<Vec<String>> {
+        match self {
+            Self::Installed => {
+                let output = Command::new("dpkg")
+                    .arg("-S")
+                    .arg(format!("*/{soname}"))
+                    .output()?;
+
+                // pkg1, pkg2:amd64: /usr/lib/libfoo.so.1
+                let mut res = vec![];
+                for line in String::from_utf8(output.stdout)?.lines() {
+                    let Some((packages, file)) = line.rsplit_once(": ") else {
+                        continue;
+                    };
+                    if !file.ends_with(&format!("/{soname}")) || !in_library_dir(file) {
+                        continue;
+                    }
+
+                    for package in packages.split(", ") {
+                        let package = package.split(':').next().unwrap_or_default().to_string();
+                        if !res.contains(&package) {
+                            res.push(package);
+                        }
+                    }
+                }
+                Ok(res)
+            }
+            Self::Contents(index) => Ok(index.get(soname).cloned().unwrap_or_default()),
+        }
+    }
+}
+
+/// Map file name => packages of files in library directories
+fn read_contents(reader: impl BufRead) -> anyhow::Result<BTreeMap<String, Vec<String>>> {
+    // usr/lib/libfoo.so.1    libs/libfoo,libs/libfoo-compat
+    let mut res: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let Some((file, packages)) = line.trim_end().rsplit_once(char::is_whitespace) else {
+            continue;
+        };
+        let file = file.trim_end();
+        if !in_library_dir(file) {
+            continue;
+        }
+        let file_name = file.rsplit('/').next().unwrap_or_default();
+
+        let providers = res.entry(file_name.to_string()).or_default();
+        for package in packages.split(',') {
+            let package = package.rsplit('/').next().unwrap_or_default().to_string();
+            if !providers.contains(&package) {
+                providers.push(package);
+            }
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENTS: &str = "\
+usr/lib/libfoo.so.1                          libs/libfoo,libs/libfoo-compat
+usr/lib/libfoo.so.1.0                        libs/libfoo
+lib/x86_64-linux-gnu/libbar.so.2             libs/libbar
+usr/lib/firefox/libxul.so                    web/firefox
+usr/share/doc/libbaz/libbaz.so.3             doc/libbaz-doc
+";
+
+    #[test]
+    fn contents_match_library_dirs_and_exact_names() {
+        let index = ProviderIndex::Contents(read_contents(CONTENTS.as_bytes()).unwrap());
+        assert_eq!(
+            index.find("libfoo.so.1").unwrap(),
+            ["libfoo", "libfoo-compat"]
+        );
+        assert_eq!(index.find("libfoo.so.1.0").unwrap(), ["libfoo"]);
+        assert_eq!(index.find("libbar.so.2").unwrap(), ["libbar"]);
+        assert!(index.find("libfoo.so").unwrap().is_empty());
+        // private and non-library directories
+        assert!(index.find("libxul.so").unwrap().is_empty());
+        assert!(index.find("libbaz.so.3").unwrap().is_empty());
+    }
+
+    #[test]
+    fn contents_of_arch_in_dir() {
+        let dir = std::env::temp_dir().join(format!("dickens-contents-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Contents-amd64"), CONTENTS).unwrap();
+        std::fs::write(
+            dir.join("Contents-arm64"),
+            "usr/lib/libarm.so.1 libs/libarm\n",
+        )
+        .unwrap();
+
+        let amd64 = ProviderIndex::from_contents(&dir, "amd64").unwrap();
+        let arm64 = ProviderIndex::from_contents(&dir, "arm64").unwrap();
+        let riscv64 = ProviderIndex::from_contents(&dir, "riscv64");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(amd64.find("libfoo.so.1.0").unwrap(), ["libfoo"]);
+        assert!(amd64.find("libarm.so.1").unwrap().is_empty());
+        assert_eq!(arm64.find("libarm.so.1").unwrap(), ["libarm"]);
+        assert!(riscv64.is_err());
+    }
+}
@@ -0,0 +1,449 @@
+//! Model of the dynamic loader search order, see ld.so(8)
+
+use super::elf::{ElfArch, ElfClass};
+use log::warn;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::{Component, Path, PathBuf},
+};
+
+const DEFAULT_DIRS: [&str; 4] = ["/usr/lib", "/usr/lib64", "/lib", "/lib64"];
+
+/// How a NEEDED entry is resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SearchPath {
+    /// NEEDED is an absolute path
+    Direct,
+    Rpath,
+    Runpath,
+    LdSoConf,
+    Default,
+    /// Found by soname in some package, but not in any directory the loader searches
+    NameOnly,
+}
+
+impl Display for SearchPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Direct => write!(f, "direct path"),
+            Self::Rpath => write!(f, "RPATH"),
+            Self::Runpath => write!(f, "RUNPATH"),
+            Self::LdSoConf => write!(f, "ld.so.conf"),
+            Self::Default => write!(f, "default path"),
+            Self::NameOnly => write!(f, "soname only"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Resolution<'a> {
+    pub path: PathBuf,
+    pub package: &'a str,
+    pub via: SearchPath,
+}
+
+/// Lexically normalize a path and fold /lib{,64} into /usr, as in merged-/usr systems
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                res.pop();
+            }
+            Component::Normal(name) => res.push(name),
+            _ => {}
+        }
+    }
+
+    if res.starts_with("/lib") || res.starts_with("/lib64") {
+        Path::new("/usr").join(res.strip_prefix("/").unwrap())
+    } else {
+        res
+    }
+}
+
+/// Debian multiarch tuple of an architecture
+fn multiarch(arch: &ElfArch) -> Option<&'static str> {
+    Some(match (arch.machine, arch.class) {
+        (3, _) => "i386-linux-gnu",
+        (62, ElfClass::Elf64) => "x86_64-linux-gnu",
+        (62, ElfClass::Elf32) => "x86_64-linux-gnux32",
+        (183, _) => "aarch64-linux-gnu",
+        (8, ElfClass::Elf64) => "mips64el-linux-gnuabi64",
+        (21, _) => "powerpc64le-linux-gnu",
+        (22, ElfClass::Elf64) => "s390x-linux-gnu",
+        (243, ElfClass::Elf64) => "riscv64-linux-gnu",
+        (258, _) => "loongarch64-linux-gnu",
+        _ => return None,
+    })
+}
+
+/// Whether `dir` is searched by the loader by default, or is a multiarch
+/// directory configured in ld.so.conf on Debian-like systems
+pub fn is_library_dir(dir: &Path) -> bool {
+    let dir = normalize(dir);
+    DEFAULT_DIRS
+        .iter()
+        .any(|default| normalize(Path::new(default)) == dir)
+        || (dir.parent() == Some(Path::new("/usr/lib"))
+            && dir
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().contains("-linux-")))
+}
+
+/// AT_PLATFORM of an architecture, for those where it does not depend on the CPU
+fn platform(arch: &ElfArch) -> Option<&'static str> {
+    Some(match (arch.machine, arch.class) {
+        (3, _) => "i686",
+        (62, ElfClass::Elf64) => "x86_64",
+        (183, _) => "aarch64",
+        _ => return None,
+    })
+}
+
+/// Expand `$ORIGIN`, `$PLATFORM` and `$LIB` in RPATH/RUNPATH entries. `$LIB`
+/// depends on how glibc is built, so all of `lib`, `lib64` and the multiarch
+/// directory are tried. Returns `None` if `$PLATFORM` is unknown for `arch`
+fn expand(dir: &str, origin: &Path, arch: &ElfArch) -> Option<Vec<PathBuf>> {
+    let origin = origin.to_string_lossy();
+    let mut dir = dir
+        .replace("${ORIGIN}", &origin)
+        .replace("$ORIGIN", &origin);
+    if dir.contains("$PLATFORM") || dir.contains("${PLATFORM}") {
+        let platform = platform(arch)?;
+        dir = dir
+            .replace("${PLATFORM}", platform)
+            .replace("$PLATFORM", platform);
+    }
+    if !dir.contains("$LIB") && !dir.contains("${LIB}") {
+        return Some(vec![normalize(Path::new(&dir))]);
+    }
+
+    let mut libs = vec!["lib".to_string(), "lib64".to_string()];
+    if let Some(multiarch) = multiarch(arch) {
+        libs.push(format!("lib/{multiarch}"));
+    }
+    Some(
+        libs.iter()
+            .map(|lib| normalize(Path::new(&dir.replace("${LIB}", lib).replace("$LIB", lib))))
+            .collect(),
+    )
+}
+
+/// Read directories from ld.so.conf, following `include` directives
+pub fn read_ld_so_conf(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    read_ld_so_conf_inner(path, &mut BTreeSet::new())
+}
+
+/// Read ld.so.conf, skipping files in `visited` to break include cycles
+fn read_ld_so_conf_inner(
+    path: &Path,
+    visited: &mut BTreeSet<PathBuf>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut res = vec![];
+    if !visited.insert(path.canonicalize().unwrap_or_else(|_| path.to_path_buf())) {
+        warn!("Skipping {} included more than once", path.display());
+        return Ok(res);
+    }
+
+    for line in std::fs::read_to_string(path)?.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let Some(pattern) = line.strip_prefix("include") else {
+            res.push(PathBuf::from(line));
+            continue;
+        };
+
+        // only `dir/*.conf` style globs are supported
+        let pattern = Path::new(pattern.trim());
+        let pattern = match pattern.is_relative() {
+            true => path.parent().unwrap_or(Path::new("/")).join(pattern),
+            false => pattern.to_path_buf(),
+        };
+        let (Some(dir), Some(file_pattern)) = (pattern.parent(), pattern.file_name()) else {
+            continue;
+        };
+        let file_pattern = file_pattern.to_string_lossy();
+        let (prefix, suffix) = file_pattern.split_once('*').unwrap_or((&file_pattern, ""));
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut includes: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                name.starts_with(prefix) && name.ends_with(suffix)
+            })
+            .collect();
+        includes.sort();
+        for include in includes {
+            match read_ld_so_conf_inner(&include, visited) {
+                Ok(dirs) => res.extend(dirs),
+                Err(err) => warn!("Failed to read {}: {}", include.display(), err),
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Resolve NEEDED entries against files of known packages
+pub struct Resolver<'a> {
+    /// Path => (package, architecture)
+    files: BTreeMap<PathBuf, (&'a str, ElfArch)>,
+    /// File name => paths
+    names: BTreeMap<String, Vec<PathBuf>>,
+    ld_so_conf: Vec<PathBuf>,
+    /// RPATH/RUNPATH entries already warned about
+    unsupported: RefCell<BTreeSet<String>>,
+}
+
+impl Default for Resolver<'_> {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl<'a> Resolver<'a> {
+    /// `ld_so_conf` are extra directories from ld.so.conf
+    pub fn new(ld_so_conf: Vec<PathBuf>) -> Self {
+        Self {
+            files: BTreeMap::new(),
+            names: BTreeMap::new(),
+            ld_so_conf: ld_so_conf.iter().map(|dir| normalize(dir)).collect(),
+            unsupported: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    /// Add a library at `path`, which is also found as `soname` in the same
+    /// directory, like the symlink created by ldconfig
+    pub fn add_file(&mut self, package: &'a str, path: &Path, soname: Option<&str>, arch: ElfArch) {
+        let path = normalize(path);
+        if let Some(soname) = soname {
+            if let Some(dir) = path.parent() {
+                self.add_path(package, dir.join(soname), arch);
+            }
+        }
+        self.add_path(package, path, arch);
+    }
+
+    fn add_path(&mut self, package: &'a str, path: PathBuf, arch: ElfArch) {
+        if let Some(name) = path.file_name() {
+            let paths = self
+                .names
+                .entry(name.to_string_lossy().to_string())
+                .or_default();
+            if !paths.contains(&path) {
+                paths.push(path.clone());
+            }
+        }
+        self.files.insert(path, (package, arch));
+    }
+
+    /// Package shipping a library compatible with `arch` at `path`
+    fn lookup(&self, path: &Path, arch: &ElfArch) -> Option<&'a str> {
+        self.files
+            .get(path)
+            .filter(|(_, file_arch)| arch.is_compatible(file_arch))
+            .map(|(package, _)| *package)
+    }
+
+    /// Directories to search for an object at `path`, in the order of the loader
+    fn search_dirs(
+        &self,
+        path: &Path,
+        rpath: &[String],
+        runpath: &[String],
+        arch: &ElfArch,
+    ) -> Vec<(PathBuf, SearchPath)> {
+        let origin = path.parent().unwrap_or(Path::new("/"));
+        let expand = |dir: &String| {
+            expand(dir, origin, arch).unwrap_or_else(|| {
+                if self.unsupported.borrow_mut().insert(dir.clone()) {
+                    warn!("Skipping {}, $PLATFORM of {} is unknown", dir, arch);
+                }
+                vec![]
+            })
+        };
+        let mut res = vec![];
+        // DT_RPATH is ignored when DT_RUNPATH is present
+        if runpath.is_empty() {
+            res.extend(
+                rpath
+                    .iter()
+                    .flat_map(|dir| expand(dir).into_iter().map(|dir| (dir, SearchPath::Rpath))),
+            );
+        }
+        res.extend(runpath.iter().flat_map(|dir| {
+            expand(dir)
+                .into_iter()
+                .map(|dir| (dir, SearchPath::Runpath))
+        }));
+        res.extend(
+            self.ld_so_conf
+                .iter()
+                .map(|dir| (dir.clone(), SearchPath::LdSoConf)),
+        );
+        res.extend(
+            DEFAULT_DIRS
+                .iter()
+                .map(|dir| (normalize(Path::new(dir)), SearchPath::Default)),
+        );
+        res
+    }
+
+    /// Resolve `needed` of the object at `path`, skipping libraries incompatible
+    /// with `arch` like the loader does. A relative path in `needed` is never
+    /// resolved, as the loader looks it up from the working directory
+    pub fn resolve(
+        &self,
+        path: &Path,
+        rpath: &[String],
+        runpath: &[String],
+        needed: &str,
+        arch: &ElfArch,
+    ) -> Option<Resolution<'a>> {
+        // NEEDED with a slash is used as a path directly
+        if needed.contains('/') {
+            if Path::new(needed).is_relative() {
+                return None;
+            }
+            let path = normalize(Path::new(needed));
+            let package = self.lookup(&path, arch)?;
+            return Some(Resolution {
+                path,
+                package,
+                via: SearchPath::Direct,
+            });
+        }
+
+        for (dir, via) in self.search_dirs(path, rpath, runpath, arch) {
+            let candidate = dir.join(needed);
+            if let Some(package) = self.lookup(&candidate, arch) {
+                return Some(Resolution {
+                    path: candidate,
+                    package,
+                    via,
+                });
+            }
+        }
+
+        self.names.get(needed)?.iter().find_map(|path| {
+            Some(Resolution {
+                path: path.clone(),
+                package: self.lookup(path, arch)?,
+                via: SearchPath::NameOnly,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X86_64: ElfArch = ElfArch {
+        class: ElfClass::Elf64,
+        machine: 62,
+        osabi: 0,
+    };
+
+    #[test]
+    fn expand_lib() {
+        let origin = Path::new("/opt/app/bin");
+        assert_eq!(
+            expand("$ORIGIN/../$LIB", origin, &X86_64).unwrap(),
+            [
+                Path::new("/opt/app/lib"),
+                Path::new("/opt/app/lib64"),
+                Path::new("/opt/app/lib/x86_64-linux-gnu"),
+            ]
+        );
+        assert_eq!(
+            expand("${ORIGIN}/../lib", origin, &X86_64).unwrap(),
+            [Path::new("/opt/app/lib")]
+        );
+    }
+
+    #[test]
+    fn expand_platform() {
+        let origin = Path::new("/opt/app/bin");
+        assert_eq!(
+            expand("$ORIGIN/../lib/$PLATFORM", origin, &X86_64).unwrap(),
+            [Path::new("/opt/app/lib/x86_64")]
+        );
+        let riscv64 = ElfArch {
+            class: ElfClass::Elf64,
+            machine: 243,
+            osabi: 0,
+        };
+        assert!(expand("/opt/${PLATFORM}", origin, &riscv64).is_none());
+
+        // not searched literally
+        let mut resolver = Resolver::default();
+        resolver.add_file(
+            "foo",
+            Path::new("/opt/$PLATFORM/libfoo.so.1"),
+            None,
+            riscv64,
+        );
+        let runpath = ["/opt/$PLATFORM".to_string()];
+        let resolution = resolver
+            .resolve(origin, &[], &runpath, "libfoo.so.1", &riscv64)
+            .unwrap();
+        assert_eq!(resolution.via, SearchPath::NameOnly);
+    }
+
+    #[test]
+    fn library_dirs() {
+        for dir in [
+            "/usr/lib",
+            "/lib64",
+            "/usr/lib/../lib",
+            "/lib/aarch64-linux-gnu",
+        ] {
+            assert!(is_library_dir(Path::new(dir)), "{}", dir);
+        }
+        for dir in ["/usr/lib/firefox", "/usr/share/libfoo", "/opt/lib"] {
+            assert!(!is_library_dir(Path::new(dir)), "{}", dir);
+        }
+    }
+
+    #[test]
+    fn resolve_needed_with_slash() {
+        let mut resolver = Resolver::default();
+        resolver.add_file("foo", Path::new("/opt/foo/libfoo.so"), None, X86_64);
+        let binary = Path::new("/opt/foo/app");
+        let resolution = resolver
+            .resolve(binary, &[], &[], "/opt/foo/libfoo.so", &X86_64)
+            .unwrap();
+        assert_eq!(resolution.package, "foo");
+        assert_eq!(resolution.via, SearchPath::Direct);
+        // relative to the working directory, not the binary
+        assert!(resolver
+            .resolve(binary, &[], &[], "./libfoo.so", &X86_64)
+            .is_none());
+    }
+
+    #[test]
+    fn ld_so_conf_include_cycle() {
+        let dir = std::env::temp_dir().join(format!("dickens-ld-so-conf-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let conf = dir.join("ld.so.conf");
+        std::fs::write(&conf, "include conf.d/*.conf\n/opt/a\n").unwrap();
+        std::fs::write(
+            dir.join("conf.d/b.conf"),
+            format!("/opt/b\ninclude {}\n", conf.display()),
+        )
+        .unwrap();
+
+        let res = read_ld_so_conf(&conf);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(res.unwrap(), [Path::new("/opt/b"), Path::new("/opt/a")]);
+    }
+}
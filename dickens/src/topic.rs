@@ -1,10 +1,13 @@
+use crate::deb::read_control;
 use libaosc::packages::{FetchPackagesAsync, FetchPackagesError, Package};
 use log::info;
 use reqwest::{Client, ClientBuilder};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use similar::TextDiff;
 use size::{Base, Size};
 use solver::PackageVersion;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Write;
 use std::io::BufReader;
 use std::{
@@ -73,7 +76,7 @@ async fn fetch_pkgs(
     Ok(real_res)
 }
 
-async fn download_pkg(client: &Client, pkg: &Package) -> anyhow::Result<PathBuf> {
+pub(crate) async fn download_pkg(client: &Client, pkg: &Package) -> anyhow::Result<PathBuf> {
     // https://georgik.rocks/how-to-download-binary-file-in-rust-by-reqwest/
     let mut out = PathBuf::new();
     out.push("debs");
@@ -127,12 +130,285 @@ async fn download_pkg(client: &Client, pkg: &Package) -> anyhow::Result<PathBuf>
 #[derive(Debug, Clone)]
 struct Res {
     package: String,
-    archs: Vec<String>,
+    arch: String,
     old_version: String,
     new_version: String,
     diff: String,
+    scripts_diff: String,
+    new_scripts: Vec<String>,
     old_size: u64,
     new_size: u64,
+    /// `None` if the package has no Installed-Size
+    old_installed_size: Option<u64>,
+    new_installed_size: Option<u64>,
+}
+
+/// Sizes of all packages of an arch in a topic
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Totals {
+    old_size: u64,
+    new_size: u64,
+    old_installed_size: u64,
+    new_installed_size: u64,
+    /// Packages left out of the installed sizes for lacking Installed-Size
+    unknown_installed: usize,
+}
+
+impl Totals {
+    fn add(&mut self, res: &Res) {
+        self.old_size += res.old_size;
+        self.new_size += res.new_size;
+        match (res.old_installed_size, res.new_installed_size) {
+            (Some(old), Some(new)) => {
+                self.old_installed_size += old;
+                self.new_installed_size += new;
+            }
+            _ => self.unknown_installed += 1,
+        }
+    }
+}
+
+/// Installed-Size of a package in bytes, libaosc reads a missing field as 0
+fn installed_bytes(pkg: &Package) -> Option<u64> {
+    // Installed-Size is in KiB
+    (pkg.installed_size != 0).then(|| pkg.installed_size.saturating_mul(1024))
+}
+
+/// Unified diff of maintainer scripts and conffiles, along with scripts that are newly added
+fn diff_control(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> (String, Vec<String>) {
+    let mut diff = String::new();
+    let mut new_scripts = vec![];
+    let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for name in names {
+        if old.get(name) == new.get(name) {
+            continue;
+        }
+
+        if !old.contains_key(name) {
+            new_scripts.push(name.clone());
+        }
+
+        let old_header = if old.contains_key(name) {
+            format!("a/{name}")
+        } else {
+            "/dev/null".to_string()
+        };
+        let new_header = if new.contains_key(name) {
+            format!("b/{name}")
+        } else {
+            "/dev/null".to_string()
+        };
+        diff.push_str(
+            &TextDiff::from_lines(
+                old.get(name).map(String::as_str).unwrap_or(""),
+                new.get(name).map(String::as_str).unwrap_or(""),
+            )
+            .unified_diff()
+            .header(&old_header, &new_header)
+            .to_string(),
+        );
+    }
+    (diff, new_scripts)
+}
+
+fn count_changes(diff: &str) -> (usize, usize) {
+    let mut added = 0;
+    let mut removed = 0;
+    for line in diff.lines() {
+        if line.starts_with("---") || line.starts_with("+++") {
+            continue;
+        } else if line.starts_with("+") {
+            added += 1;
+        } else if line.starts_with("-") {
+            removed += 1;
+        }
+    }
+    (added, removed)
+}
+
+/// A hunk of a unified diff, along with the file header it belongs to
+struct Hunk<'a> {
+    file: String,
+    /// `None` for text outside of `@@` hunks, e.g. `Only in ...` lines
+    header: Option<&'a str>,
+    body: String,
+}
+
+/// Parse `@@ -l[,s] +l[,s] @@` into (old length, new length)
+fn parse_hunk_header(line: &str) -> Option<(usize, usize)> {
+    let mut ranges = line.strip_prefix("@@ -")?.split(' ');
+    let old = ranges.next()?;
+    let new = ranges.next()?.strip_prefix('+')?;
+    let parse_len = |range: &str| -> Option<usize> {
+        match range.split_once(',') {
+            Some((start, len)) => {
+                start.parse::<usize>().ok()?;
+                len.parse().ok()
+            }
+            None => range.parse::<usize>().ok().map(|_| 1),
+        }
+    };
+    Some((parse_len(old)?, parse_len(new)?))
+}
+
+fn parse_hunks(diff: &str) -> Vec<Hunk<'_>> {
+    let mut res = vec![];
+    let mut file = String::new();
+    let mut text = String::new();
+    let mut lines = diff.lines().peekable();
+    while let Some(line) = lines.next() {
+        let is_file_header = line.starts_with("--- ");
+        let hunk_header = parse_hunk_header(line);
+        if (is_file_header || hunk_header.is_some()) && !text.is_empty() {
+            res.push(Hunk {
+                file: file.clone(),
+                header: None,
+                body: std::mem::take(&mut text),
+            });
+        }
+
+        if is_file_header {
+            file = format!("{line}\n");
+            if let Some(next) = lines.next_if(|next| next.starts_with("+++ ")) {
+                file.push_str(next);
+                file.push('\n');
+            }
+            continue;
+        }
+
+        let Some((mut old_len, mut new_len)) = hunk_header else {
+            text.push_str(line);
+            text.push('\n');
+            continue;
+        };
+
+        // consume lines according to the hunk lengths, so that removed lines
+        // starting with "--" are not mistaken as file headers
+        let mut body = String::new();
+        while let Some(next) = lines.peek() {
+            match next.chars().next() {
+                Some('\\') => {}
+                Some('-') if old_len > 0 => old_len -= 1,
+                Some('+') if new_len > 0 => new_len -= 1,
+                Some(' ') | None if old_len > 0 && new_len > 0 => {
+                    old_len -= 1;
+                    new_len -= 1;
+                }
+                _ => break,
+            }
+            body.push_str(next);
+            body.push('\n');
+            lines.next();
+        }
+
+        res.push(Hunk {
+            file: file.clone(),
+            header: Some(line),
+            body,
+        });
+    }
+    if !text.is_empty() {
+        res.push(Hunk {
+            file,
+            header: None,
+            body: text,
+        });
+    }
+    res
+}
+
+/// Merge diffs of multiple archs into one, annotating hunks that only appear on some archs
+///
+/// Only identical hunks are merged. Line numbers of different archs refer to
+/// different files, so hunks keep the order they have in each arch's diff
+/// instead of being sorted by line number.
+fn merge_diffs(diffs: &[(&str, &str)]) -> String {
+    // (hunk, archs having it)
+    let mut hunks: Vec<(Hunk, Vec<&str>)> = vec![];
+    for (arch, diff) in diffs {
+        // hunks of this arch are inserted after the previous one of this arch
+        let mut cursor = 0;
+        for hunk in parse_hunks(diff) {
+            match hunks.iter().position(|(h, archs)| {
+                h.file == hunk.file
+                    && h.header == hunk.header
+                    && h.body == hunk.body
+                    && !archs.contains(arch)
+            }) {
+                Some(pos) => {
+                    hunks[pos].1.push(arch);
+                    cursor = cursor.max(pos + 1);
+                }
+                None => {
+                    hunks.insert(cursor, (hunk, vec![arch]));
+                    cursor += 1;
+                }
+            }
+        }
+    }
+
+    let mut files: Vec<&str> = vec![];
+    for (hunk, _) in &hunks {
+        if !files.contains(&hunk.file.as_str()) {
+            files.push(&hunk.file);
+        }
+    }
+
+    let mut res = String::new();
+    for file in files {
+        res.push_str(file);
+        for (hunk, archs) in hunks.iter().filter(|(h, _)| h.file == file) {
+            let only = (archs.len() != diffs.len()).then(|| format!("{} only", archs.join(", ")));
+            match (hunk.header, only) {
+                (Some(header), Some(only)) => writeln!(res, "{header} {only}").unwrap(),
+                (Some(header), None) => writeln!(res, "{header}").unwrap(),
+                (None, Some(only)) => writeln!(res, "# {only}").unwrap(),
+                (None, None) => {}
+            }
+            res.push_str(&hunk.body);
+        }
+    }
+    res
+}
+
+/// Like `size_delta`, for sizes that may be unknown
+fn installed_delta(old_size: Option<u64>, new_size: Option<u64>) -> String {
+    match (old_size, new_size) {
+        (Some(old_size), Some(new_size)) => size_delta(old_size, new_size),
+        _ => "unknown".to_string(),
+    }
+}
+
+fn size_delta(old_size: u64, new_size: u64) -> String {
+    if new_size >= old_size {
+        if old_size != 0 {
+            format!(
+                "+{} (+{:.1}%)",
+                Size::from_bytes(new_size - old_size)
+                    .format()
+                    .with_base(Base::Base10),
+                ((new_size as f64 / old_size as f64) - 1.0) * 100.0
+            )
+        } else {
+            format!(
+                "+{}",
+                Size::from_bytes(new_size - old_size)
+                    .format()
+                    .with_base(Base::Base10),
+            )
+        }
+    } else {
+        format!(
+            "-{} (-{:.1}%)",
+            Size::from_bytes(old_size - new_size)
+                .format()
+                .with_base(Base::Base10),
+            (1.0 - (new_size as f64 / old_size as f64)) * 100.0
+        )
+    }
 }
 
 async fn handle_arch(
@@ -166,7 +442,7 @@ async fn handle_arch(
                 topic_pkg.package, found.version, topic_pkg.version
             );
 
-            let (diff, old_size, new_size) = if let Some(local_repo) = &local_repo {
+            let (left, right) = if let Some(local_repo) = &local_repo {
                 // diff directly
                 let mut left = local_repo.clone();
                 left.push("debs");
@@ -175,67 +451,65 @@ async fn handle_arch(
                 let mut right = local_repo.clone();
                 right.push("debs");
                 right.push(&topic_pkg.filename);
-
-                (
-                    Command::new("./diff-deb.sh")
-                        .arg(&left)
-                        .arg(&right)
-                        .output()?,
-                    std::fs::metadata(left)?.len(),
-                    std::fs::metadata(right)?.len(),
-                )
+                (left, right)
             } else {
                 // download topic pkg
-                let left = download_pkg(&client, found).await?;
-                let right = download_pkg(&client, &topic_pkg).await?;
                 (
-                    Command::new("./diff-deb.sh")
-                        .arg(&left)
-                        .arg(&right)
-                        .output()?,
-                    std::fs::metadata(left)?.len(),
-                    std::fs::metadata(right)?.len(),
+                    download_pkg(&client, found).await?,
+                    download_pkg(&client, &topic_pkg).await?,
                 )
             };
 
+            let diff = Command::new("./diff-deb.sh")
+                .arg(&left)
+                .arg(&right)
+                .output()?;
+            let (scripts_diff, new_scripts) =
+                diff_control(&read_control(&left)?, &read_control(&right)?);
+
             let new_res = Res {
                 package: topic_pkg.package.clone(),
-                archs: vec![topic_pkg.architecture.clone()],
+                arch: topic_pkg.architecture.clone(),
                 old_version: found.version.clone(),
                 new_version: topic_pkg.version.clone(),
                 diff: String::from_utf8_lossy(&diff.stdout).to_string(),
-                old_size,
-                new_size,
+                scripts_diff,
+                new_scripts,
+                old_size: std::fs::metadata(left)?.len(),
+                new_size: std::fs::metadata(right)?.len(),
+                old_installed_size: installed_bytes(found),
+                new_installed_size: installed_bytes(&topic_pkg),
             };
 
             res.push(new_res);
         } else {
-            let (diff, new_size) = if let Some(local_repo) = &local_repo {
+            let right = if let Some(local_repo) = &local_repo {
                 // diff directly
                 let mut path = local_repo.clone();
                 path.push("debs");
                 path.push(&topic_pkg.filename);
-                (
-                    Command::new("./diff-deb-new.sh").arg(&path).output()?,
-                    std::fs::metadata(path)?.len(),
-                )
+                path
             } else {
                 // download topic pkg
-                let right = download_pkg(&client, &topic_pkg).await?;
-                (
-                    Command::new("./diff-deb-new.sh").arg(&right).output()?,
-                    std::fs::metadata(right)?.len(),
-                )
+                download_pkg(&client, &topic_pkg).await?
             };
 
+            let diff = Command::new("./diff-deb-new.sh").arg(&right).output()?;
+            let (scripts_diff, new_scripts) =
+                diff_control(&BTreeMap::new(), &read_control(&right)?);
+
             let new_res = Res {
                 package: topic_pkg.package.clone(),
-                archs: vec![topic_pkg.architecture.clone()],
+                arch: topic_pkg.architecture.clone(),
                 old_version: "".to_string(),
                 new_version: topic_pkg.version.clone(),
                 diff: String::from_utf8_lossy(&diff.stdout).to_string(),
+                scripts_diff,
+                new_scripts,
                 old_size: 0,
-                new_size,
+                new_size: std::fs::metadata(right)?.len(),
+                old_installed_size: Some(0),
+                new_installed_size: installed_bytes(&topic_pkg),
             };
             res.push(new_res);
         }
@@ -243,9 +517,267 @@ async fn handle_arch(
     Ok(res)
 }
 
-pub async fn report(topic: &str, local_repo: Option<PathBuf>) -> anyhow::Result<String> {
+/// Structured data of a topic report, persisted to compare against later reports
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub topic: String,
+    pub packages: Vec<SnapshotEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub package: String,
+    pub arch: String,
+    pub old_version: String,
+    pub new_version: String,
+    pub old_size: u64,
+    pub new_size: u64,
+    pub old_installed_size: Option<u64>,
+    pub new_installed_size: Option<u64>,
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Describe what changed in a topic between two snapshots
+pub fn diff_snapshots(old: &Snapshot, new: &Snapshot) -> anyhow::Result<String> {
+    if old.topic != new.topic {
+        anyhow::bail!(
+            "Snapshot of topic {} cannot be compared with topic {}",
+            old.topic,
+            new.topic
+        )
+    }
     let mut report = String::new();
-    let mut res: Vec<Res> = vec![];
+
+    // package => arch => entry
+    let index = |snapshot: &'_ Snapshot| {
+        let mut res: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        for entry in &snapshot.packages {
+            res.entry(entry.package.clone())
+                .or_default()
+                .insert(entry.arch.clone(), entry.new_version.clone());
+        }
+        res
+    };
+    let old_pkgs = index(old);
+    let new_pkgs = index(new);
+
+    let mut added = vec![];
+    let mut removed = vec![];
+    let mut moved = vec![];
+    for (package, archs) in &new_pkgs {
+        let Some(old_archs) = old_pkgs.get(package) else {
+            added.push(format!(
+                "{} on {}",
+                package,
+                archs
+                    .iter()
+                    .map(|(arch, version)| format!("{arch} ({version})"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            continue;
+        };
+
+        let all_archs: BTreeSet<&String> = archs.keys().chain(old_archs.keys()).collect();
+        for arch in all_archs {
+            let old_version = old_archs.get(arch).map(String::as_str).unwrap_or("-");
+            let new_version = archs.get(arch).map(String::as_str).unwrap_or("-");
+            if old_version != new_version {
+                moved.push(format!(
+                    "{package} on {arch}: {old_version} -> {new_version}"
+                ));
+            }
+        }
+    }
+    for package in old_pkgs.keys() {
+        if !new_pkgs.contains_key(package) {
+            removed.push(package.clone());
+        }
+    }
+
+    writeln!(report, "Dickens-topic changes since last report:")?;
+    writeln!(report)?;
+    if added.is_empty() && removed.is_empty() && moved.is_empty() {
+        writeln!(report, "No changes")?;
+        return Ok(report);
+    }
+
+    for (title, lines) in [
+        ("Newly added packages", added),
+        ("Removed packages", removed),
+        ("Version changes", moved),
+    ] {
+        if lines.is_empty() {
+            continue;
+        }
+        writeln!(report, "{title}:")?;
+        writeln!(report)?;
+        for line in lines {
+            writeln!(report, "- {line}")?;
+        }
+        writeln!(report)?;
+    }
+    Ok(report)
+}
+
+/// Section of a package, with its sizes and diffs on each arch
+fn write_package(report: &mut String, package: &str, entries: &[Res]) -> std::fmt::Result {
+    let archs: Vec<&str> = entries.iter().map(|cur| cur.arch.as_str()).collect();
+    let first = &entries[0];
+    if entries
+        .iter()
+        .any(|cur| cur.old_version != first.old_version || cur.new_version != first.new_version)
+    {
+        writeln!(report, "{} updated on {}:", package, archs.join(", "))?;
+    } else if first.old_version.is_empty() {
+        writeln!(
+            report,
+            "{} introduced at {} on {}:",
+            package,
+            first.new_version,
+            archs.join(", ")
+        )?;
+    } else {
+        writeln!(
+            report,
+            "{} upgraded from {} to {} on {}:",
+            package,
+            first.old_version,
+            first.new_version,
+            archs.join(", ")
+        )?;
+    }
+
+    writeln!(report)?;
+    writeln!(
+        report,
+        "| Arch | Old version | New version | Download size | Installed size |"
+    )?;
+    writeln!(report, "| --- | --- | --- | --- | --- |")?;
+    for cur in entries {
+        writeln!(
+            report,
+            "| {} | {} | {} | {} | {} |",
+            cur.arch,
+            if cur.old_version.is_empty() {
+                "-"
+            } else {
+                &cur.old_version
+            },
+            cur.new_version,
+            size_delta(cur.old_size, cur.new_size),
+            installed_delta(cur.old_installed_size, cur.new_installed_size)
+        )?;
+    }
+    writeln!(report)?;
+
+    let mut new_scripts: Vec<&String> = entries.iter().flat_map(|cur| &cur.new_scripts).collect();
+    new_scripts.sort();
+    new_scripts.dedup();
+    if !new_scripts.is_empty() {
+        writeln!(
+            report,
+            "**New maintainer scripts: {}**",
+            new_scripts
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+    }
+
+    let diff = merge_diffs(
+        &entries
+            .iter()
+            .map(|cur| (cur.arch.as_str(), cur.diff.as_str()))
+            .collect::<Vec<_>>(),
+    );
+    let scripts_diff = merge_diffs(
+        &entries
+            .iter()
+            .map(|cur| (cur.arch.as_str(), cur.scripts_diff.as_str()))
+            .collect::<Vec<_>>(),
+    );
+    if diff.trim().is_empty() && scripts_diff.is_empty() {
+        writeln!(report, "No changes")?;
+        writeln!(report)?;
+    } else if !diff.trim().is_empty() {
+        let (added, removed) = count_changes(&diff);
+        writeln!(report, "<details>")?;
+        writeln!(
+            report,
+            "<summary>{added} added, {removed} removed</summary>",
+        )?;
+        writeln!(report)?;
+        writeln!(report, "```diff")?;
+        write!(report, "{}", diff)?;
+        writeln!(report, "```")?;
+        writeln!(report, "</details>")?;
+    }
+
+    if !scripts_diff.is_empty() {
+        let (added, removed) = count_changes(&scripts_diff);
+        writeln!(report, "<details>")?;
+        writeln!(
+            report,
+            "<summary>Maintainer scripts: {added} added, {removed} removed</summary>",
+        )?;
+        writeln!(report)?;
+        writeln!(report, "```diff")?;
+        write!(report, "{}", scripts_diff)?;
+        writeln!(report, "```")?;
+        writeln!(report, "</details>")?;
+    }
+    Ok(())
+}
+
+fn write_totals(report: &mut String, totals: &BTreeMap<String, Totals>) -> std::fmt::Result {
+    if totals.is_empty() {
+        return Ok(());
+    }
+    writeln!(report)?;
+    writeln!(report, "Topic totals:")?;
+    writeln!(report)?;
+    for (arch, total) in totals {
+        write!(
+            report,
+            "- This topic changes {arch} installs by {} and downloads by {}",
+            size_delta(total.old_installed_size, total.new_installed_size),
+            size_delta(total.old_size, total.new_size)
+        )?;
+        if total.unknown_installed > 0 {
+            write!(
+                report,
+                ", installs exclude {} packages without Installed-Size",
+                total.unknown_installed
+            )?;
+        }
+        writeln!(report)?;
+    }
+    Ok(())
+}
+
+pub async fn report(
+    topic: &str,
+    local_repo: Option<PathBuf>,
+) -> anyhow::Result<(String, Snapshot)> {
+    let mut report = String::new();
+    // package => results of each arch
+    let mut res: BTreeMap<String, Vec<Res>> = BTreeMap::new();
+    let mut totals: BTreeMap<String, Totals> = BTreeMap::new();
     let archs = [
         "all",
         "amd64",
@@ -263,109 +795,298 @@ pub async fn report(topic: &str, local_repo: Option<PathBuf>) -> anyhow::Result<
 
     for handle in handles {
         for new_res in handle.await?? {
-            // merge or insert
-            let mut insert = true;
-            for cur in &mut res {
-                if cur.package == new_res.package
-                    && cur.old_version == new_res.old_version
-                    && cur.new_version == new_res.new_version
-                    && cur.diff == new_res.diff
-                {
-                    cur.archs.extend(new_res.archs.clone());
-                    cur.old_size += new_res.old_size;
-                    cur.new_size += new_res.new_size;
-                    insert = false;
-                    break;
-                }
-            }
+            totals
+                .entry(new_res.arch.clone())
+                .or_default()
+                .add(&new_res);
 
-            if insert {
-                res.push(new_res);
-            }
+            res.entry(new_res.package.clone())
+                .or_default()
+                .push(new_res);
         }
     }
 
-    res.sort_by(|a, b| a.package.cmp(&b.package));
-
     writeln!(report, "Dickens-topic report:")?;
     writeln!(report)?;
-    for cur in &res {
-        if cur.old_version.is_empty() {
-            writeln!(
-                report,
-                "{} introduced at {} on {}:",
-                cur.package,
-                cur.new_version,
-                cur.archs.join(", ")
-            )?;
-        } else {
-            writeln!(
-                report,
-                "{} upgraded from {} to {} on {}:",
-                cur.package,
-                cur.old_version,
-                cur.new_version,
-                cur.archs.join(", ")
-            )?;
-        }
+    for (package, entries) in &res {
+        write_package(&mut report, package, entries)?;
+    }
 
-        let size_desc = if cur.new_size >= cur.old_size {
-            if cur.old_size != 0 {
-                format!(
-                    ", size +{} (+{:.1}%)",
-                    Size::from_bytes(cur.new_size - cur.old_size)
-                        .format()
-                        .with_base(Base::Base10),
-                    ((cur.new_size as f64 / cur.old_size as f64) - 1.0) * 100.0
-                )
-            } else {
-                format!(
-                    ", size +{}",
-                    Size::from_bytes(cur.new_size - cur.old_size)
-                        .format()
-                        .with_base(Base::Base10),
-                )
-            }
-        } else {
-            format!(
-                ", size -{} (-{:.1}%)",
-                Size::from_bytes(cur.old_size - cur.new_size)
-                    .format()
-                    .with_base(Base::Base10),
-                (1.0 - (cur.new_size as f64 / cur.old_size as f64)) * 100.0
-            )
-        };
+    write_totals(&mut report, &totals)?;
 
-        if cur.diff.trim().is_empty() {
-            writeln!(report)?;
-            writeln!(report, "No changes{size_desc}")?;
-            writeln!(report)?;
-            continue;
+    let snapshot = Snapshot {
+        topic: topic.to_string(),
+        packages: res
+            .into_values()
+            .flatten()
+            .map(|cur| SnapshotEntry {
+                package: cur.package,
+                arch: cur.arch,
+                old_version: cur.old_version,
+                new_version: cur.new_version,
+                old_size: cur.old_size,
+                new_size: cur.new_size,
+                old_installed_size: cur.old_installed_size,
+                new_installed_size: cur.new_installed_size,
+            })
+            .collect(),
+    };
+    Ok((report, snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "--- a/list\n+++ b/list\n";
+
+    fn control(files: &[(&str, &str)]) -> BTreeMap<String, String> {
+        files
+            .iter()
+            .map(|(name, content)| (name.to_string(), content.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn diff_control_scripts_and_conffiles() {
+        let old = control(&[
+            ("postinst", "#!/bin/sh\nldconfig\n"),
+            ("prerm", "#!/bin/sh\n"),
+            ("conffiles", "/etc/foo.conf\n"),
+        ]);
+        let new = control(&[
+            ("postinst", "#!/bin/sh\nldconfig\nsystemctl daemon-reload\n"),
+            ("triggers", "activate-noawait ldconfig\n"),
+            ("conffiles", "/etc/foo.conf\n/etc/bar.conf\n"),
+        ]);
+        let (diff, new_scripts) = diff_control(&old, &new);
+        assert_eq!(new_scripts, ["triggers"]);
+        assert_eq!(
+            diff,
+            "--- a/conffiles\n+++ b/conffiles\n@@ -1 +1,2 @@\n /etc/foo.conf\n+/etc/bar.conf\n\
+             --- a/postinst\n+++ b/postinst\n@@ -1,2 +1,3 @@\n #!/bin/sh\n ldconfig\n+systemctl daemon-reload\n\
+             --- a/prerm\n+++ /dev/null\n@@ -1 +0,0 @@\n-#!/bin/sh\n\
+             --- /dev/null\n+++ b/triggers\n@@ -0,0 +1 @@\n+activate-noawait ldconfig\n"
+        );
+        assert_eq!(count_changes(&diff), (3, 1));
+    }
+
+    fn res(arch: &str, sizes: (u64, u64), installed: (Option<u64>, Option<u64>)) -> Res {
+        Res {
+            package: "foo".to_string(),
+            arch: arch.to_string(),
+            old_version: "1".to_string(),
+            new_version: "2".to_string(),
+            diff: String::new(),
+            scripts_diff: String::new(),
+            new_scripts: vec![],
+            old_size: sizes.0,
+            new_size: sizes.1,
+            old_installed_size: installed.0,
+            new_installed_size: installed.1,
         }
+    }
 
-        writeln!(report, "<details>")?;
+    #[test]
+    fn size_deltas() {
+        assert_eq!(size_delta(1000, 1500), "+500 bytes (+50.0%)");
+        assert_eq!(size_delta(2000, 1500), "-500 bytes (-25.0%)");
+        assert_eq!(size_delta(0, 2000), "+2.00 KB");
+        assert_eq!(size_delta(2000, 0), "-2.00 KB (-100.0%)");
+        assert_eq!(installed_delta(Some(0), Some(1024)), "+1.02 KB");
+        assert_eq!(installed_delta(Some(1024), None), "unknown");
+    }
 
-        let mut added = 0;
-        let mut removed = 0;
-        for line in cur.diff.lines() {
-            if line.starts_with("---") || line.starts_with("+++") {
-                continue;
-            } else if line.starts_with("+") {
-                added += 1;
-            } else if line.starts_with("-") {
-                removed += 1;
-            }
+    #[test]
+    fn missing_installed_size() {
+        let packages: libaosc::packages::Packages = "Package: foo\nVersion: 1\nSection: libs\n\
+            Architecture: amd64\nInstalled-Size: 2\nMaintainer: Nobody <nobody@example.com>\n\
+            Filename: pool/foo_1_amd64.deb\nSize: 1\nSHA256: 00\nDescription: foo\n"
+            .as_bytes()
+            .try_into()
+            .unwrap();
+        let mut pkg = packages.0[0].clone();
+        assert_eq!(installed_bytes(&pkg), Some(2048));
+        pkg.installed_size = 0;
+        assert_eq!(installed_bytes(&pkg), None);
+    }
+
+    #[test]
+    fn totals_per_arch() {
+        let mut totals: BTreeMap<String, Totals> = BTreeMap::new();
+        for cur in [
+            res("amd64", (1000, 1500), (Some(4000), Some(3000))),
+            res("amd64", (0, 500), (Some(0), Some(1000))),
+            res("arm64", (2000, 1000), (Some(4000), None)),
+        ] {
+            totals.entry(cur.arch.clone()).or_default().add(&cur);
         }
+        assert_eq!(
+            totals["amd64"],
+            Totals {
+                old_size: 1000,
+                new_size: 2000,
+                old_installed_size: 4000,
+                new_installed_size: 4000,
+                unknown_installed: 0,
+            }
+        );
 
-        writeln!(
+        let mut report = String::new();
+        write_totals(&mut report, &totals).unwrap();
+        assert_eq!(
             report,
-            "<summary>{added} added, {removed} removed{size_desc}</summary>",
-        )?;
-        writeln!(report)?;
-        writeln!(report, "```diff")?;
-        writeln!(report, "{}", cur.diff)?;
-        writeln!(report, "```")?;
-        writeln!(report, "</details>")?;
+            "\nTopic totals:\n\n\
+             - This topic changes amd64 installs by +0 bytes (+0.0%) and downloads by +1.00 KB (+100.0%)\n\
+             - This topic changes arm64 installs by +0 bytes and downloads by -1.00 KB (-50.0%), \
+             installs exclude 1 packages without Installed-Size\n"
+        );
+    }
+
+    #[test]
+    fn package_with_only_script_changes() {
+        let mut amd64 = res("amd64", (1000, 1000), (Some(1024), Some(1024)));
+        amd64.scripts_diff = "--- a/postinst\n+++ b/postinst\n@@ -1 +1 @@\n-a\n+b\n".to_string();
+        let mut report = String::new();
+        write_package(&mut report, "foo", &[amd64]).unwrap();
+        assert!(!report.contains("No changes"));
+        assert!(report.contains("<summary>Maintainer scripts: 1 added, 1 removed</summary>"));
+
+        let mut report = String::new();
+        write_package(&mut report, "foo", &[res("amd64", (1, 1), (None, None))]).unwrap();
+        assert!(report.contains("No changes"));
+    }
+
+    fn snapshot(topic: &str, packages: &[(&str, &str, &str)]) -> Snapshot {
+        Snapshot {
+            topic: topic.to_string(),
+            packages: packages
+                .iter()
+                .map(|(package, arch, version)| SnapshotEntry {
+                    package: package.to_string(),
+                    arch: arch.to_string(),
+                    old_version: String::new(),
+                    new_version: version.to_string(),
+                    old_size: 0,
+                    new_size: 1,
+                    old_installed_size: Some(0),
+                    new_installed_size: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diff_snapshot_changes() {
+        let old = snapshot(
+            "foo-1.0",
+            &[
+                ("foo", "amd64", "1.0"),
+                ("foo", "arm64", "1.0"),
+                ("bar", "amd64", "1"),
+            ],
+        );
+        let new = snapshot(
+            "foo-1.0",
+            &[
+                ("foo", "amd64", "1.0-1"),
+                ("foo", "arm64", "1.0"),
+                ("baz", "all", "2"),
+            ],
+        );
+        assert_eq!(
+            diff_snapshots(&old, &new).unwrap(),
+            "Dickens-topic changes since last report:\n\n\
+             Newly added packages:\n\n- baz on all (2)\n\n\
+             Removed packages:\n\n- bar\n\n\
+             Version changes:\n\n- foo on amd64: 1.0 -> 1.0-1\n\n"
+        );
+        assert!(diff_snapshots(&new, &new)
+            .unwrap()
+            .ends_with("No changes\n"));
+    }
+
+    #[test]
+    fn diff_snapshot_of_other_topic() {
+        let old = snapshot("foo-1.0", &[]);
+        let new = snapshot("foo_1.0", &[]);
+        assert!(diff_snapshots(&old, &new).is_err());
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("dickens-snapshot-{}", std::process::id()))
+            .join("foo.json");
+        let saved = snapshot("foo-1.0", &[("foo", "amd64", "1.0")]);
+        saved.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(loaded.topic, "foo-1.0");
+        assert_eq!(loaded.packages.len(), 1);
+        assert_eq!(loaded.packages[0].new_version, "1.0");
+        assert_eq!(loaded.packages[0].old_installed_size, Some(0));
+        assert_eq!(loaded.packages[0].new_installed_size, None);
+    }
+
+    #[test]
+    fn diff_control_unchanged() {
+        let files = control(&[("postinst", "#!/bin/sh\n")]);
+        assert_eq!(diff_control(&files, &files), (String::new(), vec![]));
+    }
+
+    #[test]
+    fn parse_hunks_keeps_removed_dashes() {
+        let diff = format!("{FILE}@@ -1,2 +1,1 @@\n--- not a header\n keep\n");
+        let hunks = parse_hunks(&diff);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].file, FILE);
+        assert_eq!(hunks[0].header, Some("@@ -1,2 +1,1 @@"));
+        assert_eq!(hunks[0].body, "--- not a header\n keep\n");
+    }
+
+    #[test]
+    fn parse_hunks_keeps_text_outside_hunks() {
+        let diff = format!("Only in new: foo\n{FILE}@@ -1 +1 @@\n-a\n+b\nBinary files differ\n");
+        let hunks = parse_hunks(&diff);
+        assert_eq!(hunks.len(), 3);
+        assert_eq!(hunks[0].header, None);
+        assert_eq!(hunks[0].body, "Only in new: foo\n");
+        assert_eq!(hunks[1].body, "-a\n+b\n");
+        assert_eq!(hunks[2].header, None);
+        assert_eq!(hunks[2].file, FILE);
+        assert_eq!(hunks[2].body, "Binary files differ\n");
+    }
+
+    #[test]
+    fn merge_identical_hunks() {
+        let diff = format!("{FILE}@@ -1 +1 @@\n-a\n+b\n");
+        assert_eq!(merge_diffs(&[("amd64", &diff), ("arm64", &diff)]), diff);
+    }
+
+    #[test]
+    fn merge_keeps_order_of_each_arch() {
+        // the arm64-only hunk has a smaller line number, but belongs after the
+        // shared one in the arm64 diff
+        let amd64 = format!("{FILE}@@ -10 +10 @@\n-a\n+b\n@@ -20 +20 @@\n-c\n+d\n");
+        let arm64 = format!("{FILE}@@ -10 +10 @@\n-a\n+b\n@@ -5 +5 @@\n-e\n+f\n");
+        assert_eq!(
+            merge_diffs(&[("amd64", &amd64), ("arm64", &arm64)]),
+            format!(
+                "{FILE}@@ -10 +10 @@\n-a\n+b\n\
+                 @@ -5 +5 @@ arm64 only\n-e\n+f\n\
+                 @@ -20 +20 @@ amd64 only\n-c\n+d\n"
+            )
+        );
+    }
+
+    #[test]
+    fn merge_annotates_text_outside_hunks() {
+        let amd64 = "Only in new: foo\n";
+        let arm64 = "";
+        assert_eq!(
+            merge_diffs(&[("amd64", amd64), ("arm64", arm64)]),
+            "# amd64 only\nOnly in new: foo\n"
+        );
     }
-    Ok(report)
 }